@@ -1,6 +1,19 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
+mod m20261017_000002_add_event_description;
+mod m20261017_000003_add_event_updated_at;
+mod m20261017_000004_add_user_calendar_token;
+mod m20261017_000005_create_api_tokens;
+mod m20261017_000006_create_events_fts;
+mod m20261017_000007_create_locations_and_categories;
+mod m20261017_000008_add_roles_and_moderation;
+mod m20261017_000009_create_event_flags;
+mod m20261017_000010_create_password_resets;
+mod m20261017_000011_add_email_verification;
+mod m20261017_000012_add_two_factor;
+mod m20261017_000013_create_login_throttles;
+mod m20261017_000014_add_users_legacy_salt;
 
 pub struct Migrator;
 
@@ -9,6 +22,19 @@ impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
         vec![
             Box::new(m20220101_000001_create_table::Migration),
+            Box::new(m20261017_000002_add_event_description::Migration),
+            Box::new(m20261017_000003_add_event_updated_at::Migration),
+            Box::new(m20261017_000004_add_user_calendar_token::Migration),
+            Box::new(m20261017_000005_create_api_tokens::Migration),
+            Box::new(m20261017_000006_create_events_fts::Migration),
+            Box::new(m20261017_000007_create_locations_and_categories::Migration),
+            Box::new(m20261017_000008_add_roles_and_moderation::Migration),
+            Box::new(m20261017_000009_create_event_flags::Migration),
+            Box::new(m20261017_000010_create_password_resets::Migration),
+            Box::new(m20261017_000011_add_email_verification::Migration),
+            Box::new(m20261017_000012_add_two_factor::Migration),
+            Box::new(m20261017_000013_create_login_throttles::Migration),
+            Box::new(m20261017_000014_add_users_legacy_salt::Migration),
         ]
     }
 }
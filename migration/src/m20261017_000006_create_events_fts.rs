@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // External content table: the text lives in `events`, FTS5 only keeps the index
+        db.execute_unprepared(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS events_fts USING fts5(
+                title, category, location,
+                content='events', content_rowid='id',
+                tokenize='unicode61 remove_diacritics 2'
+            )",
+        )
+        .await?;
+
+        // Keep the index in step with `events`, whoever writes to it
+        db.execute_unprepared(
+            "CREATE TRIGGER IF NOT EXISTS events_fts_insert AFTER INSERT ON events BEGIN
+                INSERT INTO events_fts(rowid, title, category, location)
+                VALUES (new.id, new.title, new.category, new.location);
+            END",
+        )
+        .await?;
+        db.execute_unprepared(
+            "CREATE TRIGGER IF NOT EXISTS events_fts_delete AFTER DELETE ON events BEGIN
+                INSERT INTO events_fts(events_fts, rowid, title, category, location)
+                VALUES ('delete', old.id, old.title, old.category, old.location);
+            END",
+        )
+        .await?;
+        db.execute_unprepared(
+            "CREATE TRIGGER IF NOT EXISTS events_fts_update AFTER UPDATE ON events BEGIN
+                INSERT INTO events_fts(events_fts, rowid, title, category, location)
+                VALUES ('delete', old.id, old.title, old.category, old.location);
+                INSERT INTO events_fts(rowid, title, category, location)
+                VALUES (new.id, new.title, new.category, new.location);
+            END",
+        )
+        .await?;
+
+        // Index the events that already exist
+        db.execute_unprepared("INSERT INTO events_fts(events_fts) VALUES ('rebuild')")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("DROP TRIGGER IF EXISTS events_fts_update").await?;
+        db.execute_unprepared("DROP TRIGGER IF EXISTS events_fts_delete").await?;
+        db.execute_unprepared("DROP TRIGGER IF EXISTS events_fts_insert").await?;
+        db.execute_unprepared("DROP TABLE IF EXISTS events_fts").await?;
+        Ok(())
+    }
+}
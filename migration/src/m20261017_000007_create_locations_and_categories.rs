@@ -0,0 +1,110 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// What used to be hard-coded in `handler::models`, in the same order
+const LOCATIONS: [&str; 20] = [
+    "Stockholm",
+    "Göteborg",
+    "Malmö",
+    "Uppsala",
+    "Västerås",
+    "Örebro",
+    "Linköping",
+    "Helsingborg",
+    "Jönköping",
+    "Norrköping",
+    "Lund",
+    "Umeå",
+    "Gävle",
+    "Borås",
+    "Eskilstuna",
+    "Södertälje",
+    "Karlstad",
+    "Täby",
+    "Växjö",
+    "Halmstad",
+];
+
+const CATEGORIES: [&str; 6] = [
+    "Languages",
+    "Sports",
+    "Social",
+    "Arts and theatre",
+    "Xmas",
+    "Other",
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Locations::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Locations::Id))
+                    .col(string(Locations::Name).not_null().unique_key())
+                    // Retired entries can't be picked for new events but old events keep them
+                    .col(date_time_null(Locations::RetiredAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Categories::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Categories::Id))
+                    .col(string(Categories::Name).not_null().unique_key())
+                    .col(date_time_null(Categories::RetiredAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        let mut insert = Query::insert()
+            .into_table(Locations::Table)
+            .columns([Locations::Name])
+            .to_owned();
+        for name in LOCATIONS {
+            insert.values_panic([name.into()]);
+        }
+        manager.exec_stmt(insert).await?;
+
+        let mut insert = Query::insert()
+            .into_table(Categories::Table)
+            .columns([Categories::Name])
+            .to_owned();
+        for name in CATEGORIES {
+            insert.values_panic([name.into()]);
+        }
+        manager.exec_stmt(insert).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Categories::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Locations::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Locations {
+    Table,
+    Id,
+    Name,
+    RetiredAt,
+}
+
+#[derive(DeriveIden)]
+enum Categories {
+    Table,
+    Id,
+    Name,
+    RetiredAt,
+}
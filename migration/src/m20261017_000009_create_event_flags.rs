@@ -0,0 +1,81 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Reports of spam or fake events, waiting for a moderator until dismissed
+        manager
+            .create_table(
+                Table::create()
+                    .table(EventFlags::Table)
+                    .if_not_exists()
+                    .col(pk_auto(EventFlags::Id))
+                    .col(integer(EventFlags::EventId).not_null())
+                    .col(integer(EventFlags::UserId).not_null())
+                    .col(string(EventFlags::Reason).not_null())
+                    .col(text(EventFlags::Comment).not_null().default(""))
+                    .col(date_time(EventFlags::CreatedAt).not_null().default(SimpleExpr::Custom("CURRENT_TIMESTAMP".into())))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_event_flags_event")
+                            .from(EventFlags::Table, EventFlags::EventId)
+                            .to(Events::Table, Events::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_event_flags_user")
+                            .from(EventFlags::Table, EventFlags::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // One flag per user and event
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_event_flags_event_user")
+                    .table(EventFlags::Table)
+                    .col(EventFlags::EventId)
+                    .col(EventFlags::UserId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EventFlags::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum EventFlags {
+    Table,
+    Id,
+    EventId,
+    UserId,
+    Reason,
+    Comment,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Events {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
@@ -0,0 +1,74 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Emailed password reset links. Only a SHA-256 hash of the token is kept
+        manager
+            .create_table(
+                Table::create()
+                    .table(PasswordResets::Table)
+                    .if_not_exists()
+                    .col(pk_auto(PasswordResets::Id))
+                    .col(integer(PasswordResets::UserId).not_null())
+                    .col(string(PasswordResets::TokenHash).not_null().unique_key())
+                    .col(date_time(PasswordResets::CreatedAt).not_null().default(SimpleExpr::Custom("CURRENT_TIMESTAMP".into())))
+                    .col(date_time(PasswordResets::ExpiresAt).not_null())
+                    .col(date_time_null(PasswordResets::UsedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_password_resets_user")
+                            .from(PasswordResets::Table, PasswordResets::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Sessions remember the value at login and end once it moves on
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(integer(Users::SessionVersion).default(0))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::SessionVersion)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(PasswordResets::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PasswordResets {
+    Table,
+    Id,
+    UserId,
+    TokenHash,
+    CreatedAt,
+    ExpiresAt,
+    UsedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+    SessionVersion,
+}
@@ -0,0 +1,79 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(date_time_null(Users::EmailVerifiedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Accounts from before verification existed keep posting as they did
+        manager
+            .get_connection()
+            .execute_unprepared("UPDATE users SET email_verified_at = joined_at")
+            .await?;
+
+        // Emailed verification links, kept like password reset links
+        manager
+            .create_table(
+                Table::create()
+                    .table(EmailVerifications::Table)
+                    .if_not_exists()
+                    .col(pk_auto(EmailVerifications::Id))
+                    .col(integer(EmailVerifications::UserId).not_null())
+                    .col(string(EmailVerifications::TokenHash).not_null().unique_key())
+                    .col(date_time(EmailVerifications::CreatedAt).not_null().default(SimpleExpr::Custom("CURRENT_TIMESTAMP".into())))
+                    .col(date_time(EmailVerifications::ExpiresAt).not_null())
+                    .col(date_time_null(EmailVerifications::UsedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_email_verifications_user")
+                            .from(EmailVerifications::Table, EmailVerifications::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EmailVerifications::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::EmailVerifiedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum EmailVerifications {
+    Table,
+    Id,
+    UserId,
+    TokenHash,
+    CreatedAt,
+    ExpiresAt,
+    UsedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+    EmailVerifiedAt,
+}
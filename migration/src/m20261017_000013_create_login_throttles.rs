@@ -0,0 +1,43 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Failed login counters, one row per account email or client IP.
+        // Kept in the database so restarting the server doesn't reset them
+        manager
+            .create_table(
+                Table::create()
+                    .table(LoginThrottles::Table)
+                    .if_not_exists()
+                    .col(pk_auto(LoginThrottles::Id))
+                    .col(string(LoginThrottles::Key).not_null().unique_key())
+                    .col(integer(LoginThrottles::Failures).not_null().default(0))
+                    .col(date_time(LoginThrottles::LastFailureAt).not_null())
+                    .col(date_time(LoginThrottles::RetryAfter).not_null())
+                    .col(date_time_null(LoginThrottles::LockedUntil))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(LoginThrottles::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum LoginThrottles {
+    Table,
+    Id,
+    Key,
+    Failures,
+    LastFailureAt,
+    RetryAfter,
+    LockedUntil,
+}
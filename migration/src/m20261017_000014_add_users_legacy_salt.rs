@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use sea_orm_migration::{prelude::*, schema::*, sea_orm::{FromQueryResult, Statement}};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(FromQueryResult)]
+struct UserPassword {
+    id: i32,
+    password: String,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(boolean(Users::LegacySalt).not_null().default(false))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Hashes from before per-user salts all carry the one shared salt, so
+        // a salt used by more than one account, or equal to `PASSWORD_SALT`
+        // where that is still set, marks the account for a rehash at login
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+        let users = UserPassword::find_by_statement(Statement::from_string(
+            backend,
+            "SELECT id, password FROM users",
+        ))
+        .all(db)
+        .await?;
+
+        let shared_salt = std::env::var("PASSWORD_SALT").ok();
+        let mut accounts_per_salt: HashMap<&str, usize> = HashMap::new();
+        for user in &users {
+            if let Some(salt) = phc_salt(&user.password) {
+                *accounts_per_salt.entry(salt).or_default() += 1;
+            }
+        }
+        for user in &users {
+            let Some(salt) = phc_salt(&user.password) else {
+                continue;
+            };
+            if accounts_per_salt[salt] > 1 || shared_salt.as_deref() == Some(salt) {
+                db.execute(Statement::from_sql_and_values(
+                    backend,
+                    "UPDATE users SET legacy_salt = TRUE WHERE id = ?",
+                    [user.id.into()],
+                ))
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::LegacySalt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// The salt field of a PHC string such as `$argon2id$v=19$m=..,t=..,p=..$salt$hash`.
+fn phc_salt(hash: &str) -> Option<&str> {
+    let fields: Vec<&str> = hash.split('$').collect();
+    match fields.as_slice() {
+        ["", _, _, _, salt, _] => Some(salt),
+        _ => None,
+    }
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    LegacySalt,
+}
@@ -0,0 +1,260 @@
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, OptionalFromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serde::{Deserialize, Serialize};
+use tower_sessions::Session;
+
+use crate::AppState;
+use crate::error::{ApiError, AppError};
+use crate::entities::{api_tokens, users};
+use crate::entities::api_tokens::Entity as ApiToken;
+use crate::entities::users::Entity as User;
+use crate::helper::hash_token;
+
+/// The user an API request acts as, from an `Authorization: Bearer` token or
+/// else the `user_id` session key. Rejects with 401 when there is neither.
+pub struct AuthUser {
+    pub id: i32,
+    /// Token requests carry no cookie, so they need no CSRF token either
+    pub via_token: bool,
+}
+
+/// The token from an `Authorization: Bearer <token>` header, if any.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+impl AuthUser {
+    /// `None` when the request carries neither a token nor a logged in session.
+    async fn authenticate(parts: &mut Parts, state: &AppState) -> Result<Option<Self>, ApiError> {
+        if parts.headers.contains_key(AUTHORIZATION) {
+            // A bad token is an error, never a reason to fall back to the cookie
+            let token = bearer_token(&parts.headers).ok_or(ApiError::Unauthorized)?;
+            let Some(api_token) = ApiToken::find()
+                .filter(api_tokens::Column::TokenHash.eq(hash_token(token)))
+                .one(&state.db_connection)
+                .await?
+            else {
+                return Err(ApiError::Unauthorized);
+            };
+            let id = api_token.user_id;
+            let banned = User::find_by_id(id)
+                .one(&state.db_connection)
+                .await?
+                .is_none_or(|user| user.banned_at.is_some());
+            if banned {
+                return Err(ApiError::Unauthorized);
+            }
+            let mut active_model: api_tokens::ActiveModel = api_token.into();
+            active_model.last_used_at = Set(Some(Utc::now().naive_utc()));
+            let _ = active_model.update(&state.db_connection).await?;
+            return Ok(Some(AuthUser { id, via_token: true }));
+        }
+
+        let Ok(session) = Session::from_request_parts(parts, state).await else {
+            return Ok(None);
+        };
+        let user_id = session.get::<i32>("user_id").await.unwrap_or(None);
+        Ok(user_id.map(|id| AuthUser { id, via_token: false }))
+    }
+}
+
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        AuthUser::authenticate(parts, state).await?.ok_or(ApiError::Unauthorized)
+    }
+}
+
+/// For endpoints that work anonymously but say more to a known user. An
+/// invalid token is still rejected.
+impl OptionalFromRequestParts<AppState> for AuthUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Option<Self>, Self::Rejection> {
+        AuthUser::authenticate(parts, state).await
+    }
+}
+
+/// The address a request came from, for login throttling. Behind a reverse
+/// proxy every request comes from the proxy, so with `TRUST_PROXY` set the
+/// last `X-Forwarded-For` entry, the one the proxy added, is used instead.
+pub struct ClientIp(pub String);
+
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let forwarded = parts
+            .headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty());
+        if state.trust_proxy && let Some(ip) = forwarded {
+            return Ok(ClientIp(ip));
+        }
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+        Ok(ClientIp(peer.unwrap_or_else(|| String::from("unknown"))))
+    }
+}
+
+/// What an account may do besides managing its own events, stored as text
+/// in `users.role`. Each role can do everything the ones before it can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    /// Hides and deletes other people's events
+    Moderator,
+    /// Also manages users, locations and categories
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::User, Role::Moderator, Role::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.as_str() == value)
+    }
+
+    /// Anything unexpected in the column counts as a plain user.
+    pub fn of(user: &users::Model) -> Role {
+        Role::parse(&user.role).unwrap_or(Role::User)
+    }
+}
+
+/// The least role a `RequireRole` extractor lets through.
+pub trait MinimumRole {
+    const ROLE: Role;
+}
+
+pub struct Moderator;
+
+impl MinimumRole for Moderator {
+    const ROLE: Role = Role::Moderator;
+}
+
+pub struct Admin;
+
+impl MinimumRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// A logged in user with at least the role `R`, for the admin pages. Not
+/// logged in redirects to /login, a lesser role gets a 403 page.
+pub struct RequireRole<R> {
+    pub user: users::Model,
+    pub role: Role,
+    required: PhantomData<R>,
+}
+
+impl<R: MinimumRole> FromRequestParts<AppState> for RequireRole<R> {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let Some(user_id) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+            return Err(Redirect::to("/login").into_response());
+        };
+        let Some(user) = User::find_by_id(user_id)
+            .one(&state.db_connection)
+            .await
+            .map_err(|err| AppError::from(err).into_response())?
+        else {
+            return Err(Redirect::to("/login").into_response());
+        };
+
+        let role = Role::of(&user);
+        if role < R::ROLE {
+            let message = match R::ROLE {
+                Role::Admin => "Admins only.",
+                _ => "Moderators only.",
+            };
+            return Err(AppError::Forbidden(message).into_response());
+        }
+        Ok(RequireRole { user, role, required: PhantomData })
+    }
+}
+
+/// Logs the user in on this session. `session_version` is remembered so the
+/// session ends when the account's moves on, after a password reset.
+pub async fn start_session(session: &Session, user: &users::Model) -> Result<(), tower_sessions::session::Error> {
+    session.insert("user_id", user.id).await?;
+    session.insert("session_version", user.session_version).await
+}
+
+/// Session key for a login that passed the password check and waits for a
+/// two-factor code. `user_id` is only written once the code checks out.
+pub const PENDING_LOGIN: &str = "pending_login";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PendingLogin {
+    pub user_id: i32,
+    pub session_version: i32,
+    /// Unix time of the password check, the code has to follow soon after
+    pub started_at: i64,
+    pub failures: u32,
+}
+
+/// Starts the second login step for `user`, whose password was right.
+pub async fn begin_second_step(session: &Session, user: &users::Model) -> Result<(), tower_sessions::session::Error> {
+    session.insert(PENDING_LOGIN, PendingLogin {
+        user_id: user.id,
+        session_version: user.session_version,
+        started_at: Utc::now().timestamp(),
+        failures: 0,
+    }).await
+}
+
+/// Logs out sessions of accounts that were banned, deleted, or had their
+/// password reset since they logged in. Costs a lookup per request from a
+/// logged in user.
+pub async fn end_stale_sessions(
+    State(app_state): State<AppState>,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(user_id) = session.get::<i32>("user_id").await.unwrap_or(None) {
+        // Sessions from before versions were stored count as version 0
+        let version = session.get::<i32>("session_version").await.unwrap_or(None).unwrap_or(0);
+        let active = match User::find_by_id(user_id).one(&app_state.db_connection).await {
+            Ok(user) => user.is_some_and(|user| user.banned_at.is_none() && user.session_version == version),
+            Err(err) => return AppError::from(err).into_response(),
+        };
+        if !active && let Err(err) = session.remove::<i32>("user_id").await {
+            return AppError::from(err).into_response();
+        }
+    }
+    next.run(request).await
+}
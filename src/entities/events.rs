@@ -14,10 +14,16 @@ pub struct Model {
     pub date: String,
     pub url: String,
     pub user_id: i32,
+    #[sea_orm(column_type = "Text")]
+    pub description: String,
+    pub updated_at: Option<DateTime>,
+    pub hidden_at: Option<DateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::event_flags::Entity")]
+    EventFlags,
     #[sea_orm(has_many = "super::user_events::Entity")]
     UserEvents,
     #[sea_orm(
@@ -30,6 +36,12 @@ pub enum Relation {
     Users,
 }
 
+impl Related<super::event_flags::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::EventFlags.def()
+    }
+}
+
 impl Related<super::user_events::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::UserEvents.def()
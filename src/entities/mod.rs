@@ -1,7 +1,13 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.19
 
-pub mod prelude;
-
+pub mod api_tokens;
+pub mod categories;
+pub mod email_verifications;
+pub mod event_flags;
 pub mod events;
+pub mod locations;
+pub mod login_throttles;
+pub mod password_resets;
+pub mod recovery_codes;
 pub mod user_events;
 pub mod users;
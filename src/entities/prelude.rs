@@ -1,5 +0,0 @@
-//! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.19
-
-pub use super::events::Entity as Events;
-pub use super::user_events::Entity as UserEvents;
-pub use super::users::Entity as Users;
@@ -12,22 +12,71 @@ pub struct Model {
     pub email: String,
     pub password: String,
     pub joined_at: DateTime,
+    #[sea_orm(unique)]
+    pub calendar_token: Option<String>,
+    pub role: String,
+    pub banned_at: Option<DateTime>,
+    pub session_version: i32,
+    pub email_verified_at: Option<DateTime>,
+    pub totp_secret: Option<String>,
+    pub totp_last_step: Option<i64>,
+    pub legacy_salt: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::api_tokens::Entity")]
+    ApiTokens,
+    #[sea_orm(has_many = "super::email_verifications::Entity")]
+    EmailVerifications,
+    #[sea_orm(has_many = "super::event_flags::Entity")]
+    EventFlags,
     #[sea_orm(has_many = "super::events::Entity")]
     Events,
+    #[sea_orm(has_many = "super::password_resets::Entity")]
+    PasswordResets,
+    #[sea_orm(has_many = "super::recovery_codes::Entity")]
+    RecoveryCodes,
     #[sea_orm(has_many = "super::user_events::Entity")]
     UserEvents,
 }
 
+impl Related<super::api_tokens::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ApiTokens.def()
+    }
+}
+
+impl Related<super::email_verifications::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::EmailVerifications.def()
+    }
+}
+
+impl Related<super::event_flags::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::EventFlags.def()
+    }
+}
+
 impl Related<super::events::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Events.def()
     }
 }
 
+impl Related<super::password_resets::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PasswordResets.def()
+    }
+}
+
+impl Related<super::recovery_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RecoveryCodes.def()
+    }
+}
+
 impl Related<super::user_events::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::UserEvents.def()
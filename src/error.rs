@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use sea_orm::DbErr;
+use tera::Context;
+use tower_sessions::Session;
+
+use crate::AppState;
+use crate::csrf::csrf_token;
+use crate::helper::get_username_from_session;
+
+/// Everything a handler can fail with. Expected failures map to 4xx pages,
+/// anything else is logged and shown to the user as a generic 500 page.
+#[derive(Debug)]
+pub enum AppError {
+    NotFound,
+    Forbidden(&'static str),
+    Database(DbErr),
+    Template(tera::Error),
+    Session(tower_sessions::session::Error),
+    PasswordHash(argon2::password_hash::Error),
+    TwoFactor(String),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound => write!(f, "not found"),
+            AppError::Forbidden(message) => write!(f, "forbidden: {}", message),
+            AppError::Database(err) => write!(f, "database error: {}", err),
+            AppError::Template(err) => write!(f, "template error: {:?}", err),
+            AppError::Session(err) => write!(f, "session error: {}", err),
+            AppError::PasswordHash(err) => write!(f, "password hash error: {}", err),
+            AppError::TwoFactor(err) => write!(f, "two-factor error: {}", err),
+        }
+    }
+}
+
+impl From<DbErr> for AppError {
+    fn from(err: DbErr) -> Self {
+        AppError::Database(err)
+    }
+}
+
+impl From<tera::Error> for AppError {
+    fn from(err: tera::Error) -> Self {
+        AppError::Template(err)
+    }
+}
+
+impl From<tower_sessions::session::Error> for AppError {
+    fn from(err: tower_sessions::session::Error) -> Self {
+        AppError::Session(err)
+    }
+}
+
+impl From<argon2::password_hash::Error> for AppError {
+    fn from(err: argon2::password_hash::Error) -> Self {
+        AppError::PasswordHash(err)
+    }
+}
+
+impl From<totp_rs::TotpUrlError> for AppError {
+    fn from(err: totp_rs::TotpUrlError) -> Self {
+        AppError::TwoFactor(err.to_string())
+    }
+}
+
+impl From<qrcode::types::QrError> for AppError {
+    fn from(err: qrcode::types::QrError) -> Self {
+        AppError::TwoFactor(err.to_string())
+    }
+}
+
+/// Left on error responses so `render_error_page` knows to swap the plain
+/// text body for the styled error template.
+#[derive(Clone)]
+struct ErrorPage {
+    message: &'static str,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            AppError::NotFound => (StatusCode::NOT_FOUND, "Page not found."),
+            AppError::Forbidden(message) => (StatusCode::FORBIDDEN, message),
+            err => {
+                eprintln!("{}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Something went wrong on our side. Please try again later.",
+                )
+            }
+        };
+        let mut response = (status, message).into_response();
+        response.extensions_mut().insert(ErrorPage { message });
+        response
+    }
+}
+
+/// Errors from the JSON API under `/api/v1`. Same idea as `AppError`, but
+/// clients get `{"error": {"code", "message", "fields"}}` instead of a page.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized,
+    Forbidden(&'static str),
+    NotFound,
+    /// Field name -> messages, like the `errors` passed to the HTML forms
+    Validation(HashMap<String, Vec<String>>),
+    Internal(AppError),
+}
+
+impl<E> From<E> for ApiError
+where
+    AppError: From<E>,
+{
+    fn from(err: E) -> Self {
+        match AppError::from(err) {
+            AppError::NotFound => ApiError::NotFound,
+            AppError::Forbidden(message) => ApiError::Forbidden(message),
+            err => ApiError::Internal(err),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, code, message, fields) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, "bad_request", message, None),
+            ApiError::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                String::from("Log in or send a valid API token."),
+                None,
+            ),
+            ApiError::Forbidden(message) => (StatusCode::FORBIDDEN, "forbidden", message.to_string(), None),
+            ApiError::NotFound => (StatusCode::NOT_FOUND, "not_found", String::from("Not found."), None),
+            ApiError::Validation(fields) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation_failed",
+                String::from("Some fields are invalid."),
+                Some(fields),
+            ),
+            ApiError::Internal(err) => {
+                eprintln!("{}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal_error",
+                    String::from("Something went wrong on our side. Please try again later."),
+                    None,
+                )
+            }
+        };
+        let body = serde_json::json!({
+            "error": {
+                "code": code,
+                "message": message,
+                "fields": fields.unwrap_or_default(),
+            }
+        });
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Fallback for routes that don't exist.
+pub async fn not_found() -> AppError {
+    AppError::NotFound
+}
+
+/// Renders `partials/error.html` for responses produced by `AppError`.
+/// Lives in a middleware because `IntoResponse` has no access to Tera.
+pub async fn render_error_page(
+    State(app_state): State<AppState>,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    let Some(page) = response.extensions().get::<ErrorPage>().cloned() else {
+        return response;
+    };
+    let status = response.status();
+
+    let mut context = Context::new();
+    let is_logged_in = session.get::<i32>("user_id").await.unwrap_or(None).is_some();
+    context.insert("is_logged_in", &is_logged_in);
+    // The error may well have been the database, so don't fail twice over it
+    let logged_in_username = get_username_from_session(&session, &app_state.db_connection)
+        .await
+        .unwrap_or(None);
+    context.insert("logged_in_username", &logged_in_username);
+    context.insert("not_home", &true);
+    context.insert("csrf_token", &csrf_token(&session).await.unwrap_or_default());
+    context.insert("status_code", &status.as_u16());
+    context.insert("error_message", page.message);
+    context.insert("title", status.canonical_reason().unwrap_or("Error"));
+
+    match app_state.tera.render("partials/error.html", &context) {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(err) => {
+            eprintln!("{:?}", err);
+            response
+        }
+    }
+}
@@ -1,3 +1,14 @@
 pub mod user_handler;
 pub mod event_handler;
+pub mod calendar_handler;
+pub mod feed_handler;
+pub mod api_handler;
+pub mod settings_handler;
+pub mod two_factor_handler;
+pub mod docs_handler;
+pub mod search_handler;
+pub mod admin_handler;
+pub mod flag_handler;
+pub mod password_handler;
+pub mod verification_handler;
 pub mod models;
\ No newline at end of file
@@ -0,0 +1,638 @@
+// External crates
+use axum::{
+    routing::{get, post},
+    extract::{State, Path, Query},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Router,
+    Form,
+};
+use axum_messages::Messages;
+use chrono::Utc;
+use std::collections::HashMap;
+use sea_orm::{
+    sea_query::Expr,
+    ActiveModelTrait,
+    ColumnTrait,
+    DatabaseConnection,
+    DbErr,
+    EntityTrait,
+    PaginatorTrait,
+    QueryFilter,
+    QueryOrder,
+    QuerySelect,
+    Set,
+    TransactionTrait,
+};
+use tera::Context;
+use tower_sessions::Session;
+use validator::Validate;
+
+// Internal modules
+use crate::AppState;
+use crate::auth::{ Admin, Moderator, RequireRole, Role };
+use crate::csrf::csrf_token;
+use crate::error::AppError;
+use crate::handler::models::{ ChoiceNameData, ChoiceRow, ModerationData, Pagination, UserRoleData };
+use crate::entities::{ categories, event_flags, events, locations, users };
+use crate::entities::event_flags::Entity as EventFlag;
+use crate::entities::events::Entity as Event;
+use crate::entities::users::Entity as User;
+use crate::helper::{ field_errors, get_username_from_session };
+
+/// Users and events per page of the admin lists.
+const ADMIN_PAGE_SIZE: u64 = 50;
+/// How many of the newest accounts the dashboard shows.
+const RECENT_SIGNUPS: u64 = 10;
+
+pub fn admin_router() -> Router<AppState> {
+    Router::new()
+    .route("/admin", get(dashboard))
+    .route("/admin/users", get(list_users))
+    .route("/admin/users/{id}/role", post(change_role))
+    .route("/admin/users/{id}/ban", post(ban_user))
+    .route("/admin/users/{id}/unban", post(unban_user))
+    .route("/admin/events", get(list_all_events))
+    .route("/admin/events/{id}/hide", post(hide_event))
+    .route("/admin/events/{id}/unhide", post(unhide_event))
+    .route("/admin/events/{id}/delete", post(delete_any_event))
+    .route("/admin/{list}", get(list_choices).post(add_choice))
+    .route("/admin/{list}/{id}/rename", post(rename_choice))
+    .route("/admin/{list}/{id}/retire", post(retire_choice))
+    .route("/admin/{list}/{id}/restore", post(restore_choice))
+}
+
+pub async fn dashboard(
+    staff: RequireRole<Moderator>,
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let mut context = admin_context(&session, messages, &staff.user).await?;
+
+    context.insert("user_count", &User::find().count(db).await?);
+    context.insert("banned_count", &User::find().filter(users::Column::BannedAt.is_not_null()).count(db).await?);
+    context.insert("event_count", &Event::find().count(db).await?);
+    context.insert("hidden_count", &Event::find().filter(events::Column::HiddenAt.is_not_null()).count(db).await?);
+    let flagged = EventFlag::find()
+        .select_only()
+        .column(event_flags::Column::EventId)
+        .distinct()
+        .into_tuple::<i32>()
+        .all(db)
+        .await?;
+    context.insert("flagged_count", &flagged.len());
+
+    if staff.role >= Role::Admin {
+        let users = User::find()
+            .order_by_desc(users::Column::JoinedAt)
+            .order_by_desc(users::Column::Id)
+            .limit(RECENT_SIGNUPS)
+            .all(db)
+            .await?;
+        context.insert("recent_signups", &user_rows(db, users).await?);
+    }
+    context.insert("title", "Admin");
+    Ok(Html(app_state.tera.render("partials/admin.html", &context)?).into_response())
+}
+
+pub async fn list_users(
+    admin: RequireRole<Admin>,
+    State(app_state): State<AppState>,
+    Query(pagination): Query<Pagination>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let mut context = admin_context(&session, messages, &admin.user).await?;
+
+    let page = pagination.page.max(1);
+    let paginator = User::find()
+        .order_by_desc(users::Column::JoinedAt)
+        .order_by_desc(users::Column::Id)
+        .paginate(db, ADMIN_PAGE_SIZE);
+    let num_pages = paginator.num_pages().await?;
+    let users = paginator.fetch_page(page - 1).await?;
+
+    context.insert("users", &user_rows(db, users).await?);
+    context.insert("roles", &Role::ALL.map(Role::as_str));
+    context.insert("page", &page);
+    context.insert("num_pages", &num_pages);
+    context.insert("title", "Users");
+    Ok(Html(app_state.tera.render("partials/admin_users.html", &context)?).into_response())
+}
+
+pub async fn change_role(
+    admin: RequireRole<Admin>,
+    State(app_state): State<AppState>,
+    Path(user_id): Path<i32>,
+    messages: Messages,
+    Form(data): Form<UserRoleData>,
+) -> Result<Response, AppError> {
+    let Some(role) = Role::parse(&data.role) else {
+        messages.error("Unknown role.");
+        return Ok(Redirect::to("/admin/users").into_response());
+    };
+    // Also keeps the last admin from locking everyone out
+    if user_id == admin.user.id {
+        messages.error("You can't change your own role.");
+        return Ok(Redirect::to("/admin/users").into_response());
+    }
+    let Some(user) = User::find_by_id(user_id).one(&app_state.db_connection).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    let username = user.username.clone();
+    let mut active_model: users::ActiveModel = user.into();
+    active_model.role = Set(role.as_str().to_string());
+    let _ = active_model.update(&app_state.db_connection).await?;
+    messages.info(format!("{} is now a {}.", username, role.as_str()));
+    Ok(Redirect::to("/admin/users").into_response())
+}
+
+/// Banned users are logged out, can't log back in and their API tokens stop
+/// working. Their events stay up unless hidden separately.
+pub async fn ban_user(
+    admin: RequireRole<Admin>,
+    State(app_state): State<AppState>,
+    Path(user_id): Path<i32>,
+    messages: Messages,
+    Form(data): Form<ModerationData>,
+) -> Result<Response, AppError> {
+    let back = back_to(&data, "/admin/users");
+    let Some(user) = User::find_by_id(user_id).one(&app_state.db_connection).await? else {
+        return Err(AppError::NotFound);
+    };
+    if user.id == admin.user.id || Role::of(&user) >= Role::Admin {
+        messages.error("Admins can't be banned, change their role first.");
+        return Ok(Redirect::to(&back).into_response());
+    }
+
+    let username = user.username.clone();
+    let mut active_model: users::ActiveModel = user.into();
+    active_model.banned_at = Set(Some(Utc::now().naive_utc()));
+    let _ = active_model.update(&app_state.db_connection).await?;
+    messages.info(format!("Banned {}.", username));
+    Ok(Redirect::to(&back).into_response())
+}
+
+pub async fn unban_user(
+    _admin: RequireRole<Admin>,
+    State(app_state): State<AppState>,
+    Path(user_id): Path<i32>,
+    messages: Messages,
+    Form(data): Form<ModerationData>,
+) -> Result<Response, AppError> {
+    let back = back_to(&data, "/admin/users");
+    let Some(user) = User::find_by_id(user_id).one(&app_state.db_connection).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    let username = user.username.clone();
+    let mut active_model: users::ActiveModel = user.into();
+    active_model.banned_at = Set(None);
+    let _ = active_model.update(&app_state.db_connection).await?;
+    messages.info(format!("Unbanned {}.", username));
+    Ok(Redirect::to(&back).into_response())
+}
+
+/// Every event, hidden ones included, newest first.
+pub async fn list_all_events(
+    staff: RequireRole<Moderator>,
+    State(app_state): State<AppState>,
+    Query(pagination): Query<Pagination>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let mut context = admin_context(&session, messages, &staff.user).await?;
+
+    let page = pagination.page.max(1);
+    let paginator = Event::find()
+        .find_also_related(User)
+        .order_by_desc(events::Column::CreatedAt)
+        .order_by_desc(events::Column::Id)
+        .paginate(db, ADMIN_PAGE_SIZE);
+    let num_pages = paginator.num_pages().await?;
+    let rows: Vec<serde_json::Value> = paginator
+        .fetch_page(page - 1)
+        .await?
+        .into_iter()
+        .map(|(event, user)| serde_json::json!({
+            "id": event.id,
+            "title": event.title,
+            "date": event.date,
+            "location": event.location,
+            "category": event.category,
+            "username": user.map(|u| u.username).unwrap_or_else(|| String::from("unknown")),
+            "created_at": event.created_at.format("%Y-%m-%d %H:%M").to_string(),
+            "hidden": event.hidden_at.is_some(),
+        }))
+        .collect();
+
+    context.insert("events", &rows);
+    context.insert("page", &page);
+    context.insert("num_pages", &num_pages);
+    context.insert("title", "Events");
+    Ok(Html(app_state.tera.render("partials/admin_events.html", &context)?).into_response())
+}
+
+/// Takes an event out of every listing, search and feed without deleting it.
+pub async fn hide_event(
+    _staff: RequireRole<Moderator>,
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    messages: Messages,
+    Form(data): Form<ModerationData>,
+) -> Result<Response, AppError> {
+    set_hidden(&app_state, event_id, messages, &data, true).await
+}
+
+pub async fn unhide_event(
+    _staff: RequireRole<Moderator>,
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    messages: Messages,
+    Form(data): Form<ModerationData>,
+) -> Result<Response, AppError> {
+    set_hidden(&app_state, event_id, messages, &data, false).await
+}
+
+async fn set_hidden(
+    app_state: &AppState,
+    event_id: i32,
+    messages: Messages,
+    data: &ModerationData,
+    hidden: bool,
+) -> Result<Response, AppError> {
+    let Some(event) = Event::find_by_id(event_id).one(&app_state.db_connection).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    let title = event.title.clone();
+    let mut active_model: events::ActiveModel = event.into();
+    active_model.hidden_at = Set(hidden.then(|| Utc::now().naive_utc()));
+    let _ = active_model.update(&app_state.db_connection).await?;
+    if hidden {
+        messages.info(format!("Hid \"{}\".", title));
+    } else {
+        messages.info(format!("\"{}\" is visible again.", title));
+    }
+    Ok(Redirect::to(&back_to(data, &format!("/event/{}", event_id))).into_response())
+}
+
+pub async fn delete_any_event(
+    _staff: RequireRole<Moderator>,
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    messages: Messages,
+    Form(data): Form<ModerationData>,
+) -> Result<Response, AppError> {
+    let Some(event) = Event::find_by_id(event_id).one(&app_state.db_connection).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    let title = event.title.clone();
+    let active_model: events::ActiveModel = event.into();
+    let _ = active_model.delete(&app_state.db_connection).await?;
+    messages.info(format!("Deleted \"{}\".", title));
+    Ok(Redirect::to(&back_to(&data, "/admin/events")).into_response())
+}
+
+/// Where a moderation button returns to. Only admin pages are accepted, so
+/// the field can't be used to send someone off site.
+fn back_to(data: &ModerationData, default: &str) -> String {
+    match &data.back {
+        Some(back) if back.starts_with("/admin") => back.clone(),
+        _ => default.to_string(),
+    }
+}
+
+/// Users as the admin pages show them, with how many events each posted.
+async fn user_rows(db: &DatabaseConnection, users: Vec<users::Model>) -> Result<Vec<serde_json::Value>, DbErr> {
+    let ids: Vec<i32> = users.iter().map(|user| user.id).collect();
+    let counts: HashMap<i32, i64> = Event::find()
+        .select_only()
+        .column(events::Column::UserId)
+        .column_as(events::Column::Id.count(), "event_count")
+        .filter(events::Column::UserId.is_in(ids))
+        .group_by(events::Column::UserId)
+        .into_tuple::<(i32, i64)>()
+        .all(db)
+        .await?
+        .into_iter()
+        .collect();
+
+    Ok(users
+        .into_iter()
+        .map(|user| serde_json::json!({
+            "id": user.id,
+            "username": user.username,
+            "email": user.email,
+            "role": Role::of(&user).as_str(),
+            "joined_at": user.joined_at.format("%Y-%m-%d %H:%M").to_string(),
+            "banned": user.banned_at.is_some(),
+            "event_count": counts.get(&user.id).copied().unwrap_or(0),
+        }))
+        .collect())
+}
+
+/// What every admin page puts in its context besides its own content.
+pub async fn admin_context(session: &Session, messages: Messages, user: &users::Model) -> Result<Context, AppError> {
+    let mut context = Context::new();
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+    context.insert("is_logged_in", &true);
+    context.insert("logged_in_username", &user.username);
+    context.insert("not_home", &true);
+    context.insert("is_admin", &(Role::of(user) >= Role::Admin));
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(session).await?);
+    Ok(context)
+}
+
+/// The two lists of names events pick from. Both tables look the same, the
+/// difference is which `events` column holds the name.
+#[derive(Clone, Copy)]
+enum ChoiceList {
+    Locations,
+    Categories,
+}
+
+impl ChoiceList {
+    fn from_path(list: &str) -> Result<Self, AppError> {
+        match list {
+            "locations" => Ok(ChoiceList::Locations),
+            "categories" => Ok(ChoiceList::Categories),
+            _ => Err(AppError::NotFound),
+        }
+    }
+
+    fn path(self) -> &'static str {
+        match self {
+            ChoiceList::Locations => "locations",
+            ChoiceList::Categories => "categories",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ChoiceList::Locations => "Locations",
+            ChoiceList::Categories => "Categories",
+        }
+    }
+
+    fn event_column(self) -> events::Column {
+        match self {
+            ChoiceList::Locations => events::Column::Location,
+            ChoiceList::Categories => events::Column::Category,
+        }
+    }
+
+    /// Every entry with how many events use it, retired ones included.
+    async fn rows(self, db: &DatabaseConnection) -> Result<Vec<ChoiceRow>, DbErr> {
+        let entries: Vec<(i32, String, bool)> = match self {
+            ChoiceList::Locations => locations::Entity::find()
+                .order_by_asc(locations::Column::Id)
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|l| (l.id, l.name, l.retired_at.is_some()))
+                .collect(),
+            ChoiceList::Categories => categories::Entity::find()
+                .order_by_asc(categories::Column::Id)
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|c| (c.id, c.name, c.retired_at.is_some()))
+                .collect(),
+        };
+
+        let counts: HashMap<String, i64> = Event::find()
+            .select_only()
+            .column(self.event_column())
+            .column_as(events::Column::Id.count(), "event_count")
+            .group_by(self.event_column())
+            .into_tuple::<(String, i64)>()
+            .all(db)
+            .await?
+            .into_iter()
+            .collect();
+
+        Ok(entries
+            .into_iter()
+            .map(|(id, name, retired)| ChoiceRow {
+                event_count: counts.get(&name).copied().unwrap_or(0),
+                id,
+                name,
+                retired,
+            })
+            .collect())
+    }
+
+    async fn name_of(self, db: &DatabaseConnection, id: i32) -> Result<Option<String>, DbErr> {
+        Ok(match self {
+            ChoiceList::Locations => locations::Entity::find_by_id(id).one(db).await?.map(|l| l.name),
+            ChoiceList::Categories => categories::Entity::find_by_id(id).one(db).await?.map(|c| c.name),
+        })
+    }
+
+    async fn name_taken(self, db: &DatabaseConnection, name: &str) -> Result<bool, DbErr> {
+        let count = match self {
+            ChoiceList::Locations => locations::Entity::find()
+                .filter(locations::Column::Name.eq(name))
+                .count(db)
+                .await?,
+            ChoiceList::Categories => categories::Entity::find()
+                .filter(categories::Column::Name.eq(name))
+                .count(db)
+                .await?,
+        };
+        Ok(count > 0)
+    }
+}
+
+pub async fn list_choices(
+    _admin: RequireRole<Admin>,
+    State(app_state): State<AppState>,
+    Path(list): Path<String>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let list = ChoiceList::from_path(&list)?;
+    render_choices(&app_state, &session, messages, list, &ChoiceNameData::default(), &HashMap::new()).await
+}
+
+pub async fn add_choice(
+    _admin: RequireRole<Admin>,
+    State(app_state): State<AppState>,
+    Path(list): Path<String>,
+    messages: Messages,
+    session: Session,
+    Form(data): Form<ChoiceNameData>,
+) -> Result<Response, AppError> {
+    let list = ChoiceList::from_path(&list)?;
+
+    let data = ChoiceNameData { name: data.name.trim().to_string() };
+    if let Err(errors) = data.validate() {
+        return render_choices(&app_state, &session, messages, list, &data, &field_errors(&errors)).await;
+    }
+    let db = &app_state.db_connection;
+    if list.name_taken(db, &data.name).await? {
+        let errors = HashMap::from([
+            (String::from("name"), vec![format!("\"{}\" is already in the list.", data.name)]),
+        ]);
+        return render_choices(&app_state, &session, messages, list, &data, &errors).await;
+    }
+
+    match list {
+        ChoiceList::Locations => {
+            let _ = locations::ActiveModel { name: Set(data.name.clone()), ..Default::default() }.insert(db).await?;
+        }
+        ChoiceList::Categories => {
+            let _ = categories::ActiveModel { name: Set(data.name.clone()), ..Default::default() }.insert(db).await?;
+        }
+    }
+    messages.info(format!("Added \"{}\".", data.name));
+    Ok(Redirect::to(&format!("/admin/{}", list.path())).into_response())
+}
+
+/// Renames the entry and every event using it, so filters and links keep
+/// working.
+pub async fn rename_choice(
+    _admin: RequireRole<Admin>,
+    State(app_state): State<AppState>,
+    Path((list, id)): Path<(String, i32)>,
+    messages: Messages,
+    Form(data): Form<ChoiceNameData>,
+) -> Result<Response, AppError> {
+    let list = ChoiceList::from_path(&list)?;
+    let db = &app_state.db_connection;
+    let Some(old_name) = list.name_of(db, id).await? else {
+        return Err(AppError::NotFound);
+    };
+    let back = Redirect::to(&format!("/admin/{}", list.path())).into_response();
+
+    let data = ChoiceNameData { name: data.name.trim().to_string() };
+    if let Err(errors) = data.validate() {
+        let message: Vec<String> = field_errors(&errors).into_values().flatten().collect();
+        messages.error(message.join(" "));
+        return Ok(back);
+    }
+    if data.name == old_name {
+        return Ok(back);
+    }
+    if list.name_taken(db, &data.name).await? {
+        messages.error(format!("\"{}\" is already in the list.", data.name));
+        return Ok(back);
+    }
+
+    let txn = db.begin().await?;
+    match list {
+        ChoiceList::Locations => {
+            let _ = locations::ActiveModel { id: Set(id), name: Set(data.name.clone()), ..Default::default() }.update(&txn).await?;
+        }
+        ChoiceList::Categories => {
+            let _ = categories::ActiveModel { id: Set(id), name: Set(data.name.clone()), ..Default::default() }.update(&txn).await?;
+        }
+    }
+    let _ = Event::update_many()
+        .col_expr(list.event_column(), Expr::value(data.name.clone()))
+        .filter(list.event_column().eq(old_name.as_str()))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+
+    messages.info(format!("Renamed \"{}\" to \"{}\".", old_name, data.name));
+    Ok(back)
+}
+
+/// Retired entries can't be picked for new events, existing events keep them.
+pub async fn retire_choice(
+    _admin: RequireRole<Admin>,
+    State(app_state): State<AppState>,
+    Path((list, id)): Path<(String, i32)>,
+    messages: Messages,
+) -> Result<Response, AppError> {
+    set_retired(app_state, list, id, messages, true).await
+}
+
+pub async fn restore_choice(
+    _admin: RequireRole<Admin>,
+    State(app_state): State<AppState>,
+    Path((list, id)): Path<(String, i32)>,
+    messages: Messages,
+) -> Result<Response, AppError> {
+    set_retired(app_state, list, id, messages, false).await
+}
+
+async fn set_retired(
+    app_state: AppState,
+    list: String,
+    id: i32,
+    messages: Messages,
+    retired: bool,
+) -> Result<Response, AppError> {
+    let list = ChoiceList::from_path(&list)?;
+    let db = &app_state.db_connection;
+    let Some(name) = list.name_of(db, id).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    let retired_at = retired.then(|| Utc::now().naive_utc());
+    match list {
+        ChoiceList::Locations => {
+            let _ = locations::ActiveModel { id: Set(id), retired_at: Set(retired_at), ..Default::default() }.update(db).await?;
+        }
+        ChoiceList::Categories => {
+            let _ = categories::ActiveModel { id: Set(id), retired_at: Set(retired_at), ..Default::default() }.update(db).await?;
+        }
+    }
+    if retired {
+        messages.info(format!("Retired \"{}\".", name));
+    } else {
+        messages.info(format!("Restored \"{}\".", name));
+    }
+    Ok(Redirect::to(&format!("/admin/{}", list.path())).into_response())
+}
+
+async fn render_choices(
+    app_state: &AppState,
+    session: &Session,
+    messages: Messages,
+    list: ChoiceList,
+    form: &ChoiceNameData,
+    errors: &HashMap<String, Vec<String>>,
+) -> Result<Response, AppError> {
+    let tera = &app_state.tera;
+    let mut context = Context::new();
+
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+
+    context.insert("is_logged_in", &true);
+    let logged_in_username = get_username_from_session(session, &app_state.db_connection).await?;
+    context.insert("logged_in_username", &logged_in_username);
+    context.insert("not_home", &true);
+
+    context.insert("is_admin", &true);
+    context.insert("choices", &list.rows(&app_state.db_connection).await?);
+    context.insert("list_path", list.path());
+    context.insert("list_title", list.title());
+    context.insert("form", form);
+    context.insert("errors", errors);
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(session).await?);
+    context.insert("title", list.title());
+    let html = Html(tera.render("partials/admin_choices.html", &context)?);
+    if errors.is_empty() {
+        Ok(html.into_response())
+    } else {
+        Ok((StatusCode::UNPROCESSABLE_ENTITY, html).into_response())
+    }
+}
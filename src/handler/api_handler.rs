@@ -0,0 +1,256 @@
+// External crates
+use axum::{
+    routing::{get, post},
+    extract::{rejection::JsonRejection, State, Path, Query},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+    Router,
+};
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{
+    ActiveModelTrait,
+    ColumnTrait,
+    DatabaseConnection,
+    EntityTrait,
+    PaginatorTrait,
+    QueryFilter,
+    Set,
+};
+use tower_sessions::Session;
+use validator::ValidateArgs;
+
+// Internal modules
+use crate::AppState;
+use crate::auth::AuthUser;
+use crate::csrf::csrf_token;
+use crate::error::ApiError;
+use crate::handler::event_handler::{ fetch_event_page, toggle_going };
+use crate::handler::verification_handler::UNVERIFIED_EMAIL;
+use crate::handler::models::{ ApiEvent, ApiEventPage, ApiRsvp, ApiUser, EventFilter, NewEventData, Pagination };
+use crate::entities::events;
+use crate::entities::events::Entity as Event;
+use crate::entities::user_events;
+use crate::entities::user_events::Entity as UserEvent;
+use crate::entities::users::Entity as User;
+use crate::helper::{ email_verified, event_choices, field_errors };
+
+/// JSON versions of the event pages, nested under `/api/v1`. Clients
+/// authenticate with a personal token (`Authorization: Bearer`), or with the
+/// site's session cookie plus the `X-CSRF-Token` header from `GET /api/v1/me`.
+pub fn api_router() -> Router<AppState> {
+    Router::new()
+    .route("/events", get(list_events).post(create_event))
+    .route("/events/{id}", get(get_event).put(update_event).delete(delete_event))
+    .route("/events/{id}/rsvp", post(toggle_rsvp))
+    .route("/me", get(current_user))
+    .fallback(not_found)
+}
+
+async fn not_found() -> ApiError {
+    ApiError::NotFound
+}
+
+pub async fn list_events(
+    State(app_state): State<AppState>,
+    Query(filter): Query<EventFilter>,
+    Query(pagination): Query<Pagination>,
+    user: Option<AuthUser>,
+) -> Result<Json<ApiEventPage>, ApiError> {
+    let choices = event_choices(&app_state.db_connection, true).await?;
+    if let Err(errors) = filter.validate_with_args(&choices) {
+        return Err(ApiError::Validation(field_errors(&errors)));
+    }
+    let user_id = user.map(|user| user.id);
+    let page = pagination.page.max(1);
+    let (events, num_pages) = fetch_event_page(&app_state.db_connection, &filter, false, page, user_id)
+        .await?;
+    Ok(Json(ApiEventPage { events, page, num_pages }))
+}
+
+pub async fn get_event(
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    user: Option<AuthUser>,
+) -> Result<Json<ApiEvent>, ApiError> {
+    let user_id = user.map(|user| user.id);
+    let Some(event) = Event::find_by_id(event_id)
+        .one(&app_state.db_connection)
+        .await?
+    else {
+        return Err(ApiError::NotFound);
+    };
+    // Hidden events are only there for their owner
+    if event.hidden_at.is_some() && user_id != Some(event.user_id) {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(api_event(&app_state.db_connection, event, user_id).await?))
+}
+
+pub async fn create_event(
+    State(app_state): State<AppState>,
+    user: AuthUser,
+    data: Result<Json<NewEventData>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let uid = user.id;
+    let Json(data) = data.map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+    if !email_verified(&app_state.db_connection, uid).await? {
+        return Err(ApiError::Forbidden(UNVERIFIED_EMAIL));
+    }
+    let choices = event_choices(&app_state.db_connection, false).await?;
+    if let Err(errors) = data.validate_with_args(&choices) {
+        return Err(ApiError::Validation(field_errors(&errors)));
+    }
+
+    let new_event = events::ActiveModel {
+        title: Set(data.title),
+        url: Set(data.url),
+        location: Set(data.location),
+        date: Set(data.date),
+        category: Set(data.category),
+        description: Set(data.description),
+        user_id: Set(uid),
+        ..Default::default()
+    };
+    let event = new_event.insert(&app_state.db_connection).await?;
+    let location = format!("/api/v1/events/{}", event.id);
+    let body = api_event(&app_state.db_connection, event, Some(uid)).await?;
+    Ok((StatusCode::CREATED, [(header::LOCATION, location)], Json(body)).into_response())
+}
+
+pub async fn update_event(
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    user: AuthUser,
+    data: Result<Json<NewEventData>, JsonRejection>,
+) -> Result<Json<ApiEvent>, ApiError> {
+    let uid = user.id;
+    let event = owned_event(&app_state.db_connection, event_id, uid).await?;
+    let Json(data) = data.map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+    let choices = event_choices(&app_state.db_connection, false)
+        .await?
+        .allow(&event.location, &event.category);
+    if let Err(errors) = data.validate_with_args(&choices) {
+        return Err(ApiError::Validation(field_errors(&errors)));
+    }
+
+    let mut active_model: events::ActiveModel = event.into();
+    active_model.title = Set(data.title);
+    active_model.url = Set(data.url);
+    active_model.location = Set(data.location);
+    active_model.date = Set(data.date);
+    active_model.category = Set(data.category);
+    active_model.description = Set(data.description);
+    active_model.updated_at = Set(Some(Utc::now().naive_utc()));
+    let event = active_model.update(&app_state.db_connection).await?;
+    Ok(Json(api_event(&app_state.db_connection, event, Some(uid)).await?))
+}
+
+pub async fn delete_event(
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    user: AuthUser,
+) -> Result<StatusCode, ApiError> {
+    let uid = user.id;
+    let event = owned_event(&app_state.db_connection, event_id, uid).await?;
+    let active_model: events::ActiveModel = event.into();
+    let _ = active_model.delete(&app_state.db_connection).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Same toggle as the "going" button on the site.
+pub async fn toggle_rsvp(
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    user: AuthUser,
+) -> Result<Json<ApiRsvp>, ApiError> {
+    let uid = user.id;
+    if Event::find_by_id(event_id)
+        .filter(events::Column::HiddenAt.is_null())
+        .one(&app_state.db_connection)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::NotFound);
+    }
+
+    let is_going = toggle_going(&app_state.db_connection, uid, event_id).await?;
+    let attendee_count = UserEvent::find()
+        .filter(user_events::Column::EventId.eq(event_id))
+        .count(&app_state.db_connection)
+        .await?;
+    Ok(Json(ApiRsvp { event_id, is_going, attendee_count }))
+}
+
+pub async fn current_user(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+    session: Session,
+) -> Result<Json<ApiUser>, ApiError> {
+    let Some(user) = User::find_by_id(auth.id)
+        .one(&app_state.db_connection)
+        .await?
+    else {
+        return Err(ApiError::Unauthorized);
+    };
+    let csrf_token = if auth.via_token {
+        None
+    } else {
+        Some(csrf_token(&session).await?)
+    };
+    Ok(Json(ApiUser {
+        id: user.id,
+        username: user.username,
+        email: user.email,
+        csrf_token,
+    }))
+}
+
+/// Loads an event the user is allowed to change, i.e. one they created.
+async fn owned_event(db: &DatabaseConnection, event_id: i32, user_id: i32) -> Result<events::Model, ApiError> {
+    let Some(event) = Event::find_by_id(event_id).one(db).await? else {
+        return Err(ApiError::NotFound);
+    };
+    if event.user_id != user_id {
+        return Err(ApiError::Forbidden("You can only change your own events."));
+    }
+    Ok(event)
+}
+
+async fn api_event(db: &DatabaseConnection, event: events::Model, viewer: Option<i32>) -> Result<ApiEvent, ApiError> {
+    let username = User::find_by_id(event.user_id)
+        .one(db)
+        .await?
+        .map(|user| user.username)
+        .unwrap_or_else(|| String::from("unknown"));
+    let attendee_count = UserEvent::find()
+        .filter(user_events::Column::EventId.eq(event.id))
+        .count(db)
+        .await?;
+    let is_going = match viewer {
+        Some(uid) => UserEvent::find()
+            .filter(user_events::Column::EventId.eq(event.id))
+            .filter(user_events::Column::UserId.eq(uid))
+            .count(db)
+            .await? > 0,
+        None => false,
+    };
+    Ok(ApiEvent {
+        id: event.id,
+        title: event.title,
+        url: event.url,
+        location: event.location,
+        date: event.date,
+        category: event.category,
+        description: event.description,
+        username,
+        attendee_count,
+        is_going,
+        created_at: rfc3339(event.created_at),
+        updated_at: event.updated_at.map(rfc3339),
+    })
+}
+
+fn rfc3339(timestamp: NaiveDateTime) -> String {
+    timestamp.and_utc().to_rfc3339()
+}
@@ -0,0 +1,220 @@
+// External crates
+use axum::{
+    routing::{get, post},
+    extract::{State, Path},
+    http::header,
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_messages::Messages;
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use sea_orm::{
+    ActiveModelTrait,
+    ColumnTrait,
+    DatabaseConnection,
+    EntityTrait,
+    QueryFilter,
+    QueryOrder,
+    Set,
+    sea_query::Query as SqlQuery,
+};
+use tower_sessions::Session;
+
+// Internal modules
+use crate::AppState;
+use crate::error::AppError;
+use crate::entities::events;
+use crate::entities::events::Entity as Event;
+use crate::entities::user_events;
+use crate::entities::users;
+use crate::entities::users::Entity as User;
+use crate::helper::random_token;
+
+pub fn calendar_router() -> Router<AppState> {
+    Router::new()
+    .route("/calendar/reset", post(reset_calendar_token))
+    .route("/calendar/{feed}", get(calendar_feed))
+}
+
+/// `/event/{id}.ics`, reached through the `/event/{id}` route because the
+/// router can't match a suffix after a path parameter.
+pub async fn event_ics(app_state: &AppState, event_id: i32) -> Result<Response, AppError> {
+    let Some(event) = Event::find_by_id(event_id)
+        .filter(events::Column::HiddenAt.is_null())
+        .one(&app_state.db_connection)
+        .await?
+    else {
+        return Err(AppError::NotFound);
+    };
+
+    let mut lines = calendar_header(&event.title);
+    lines.extend(vevent(&event, &app_state.base_url));
+    lines.push(String::from("END:VCALENDAR"));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, String::from("text/calendar; charset=utf-8")),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"event-{}.ics\"", event.id)),
+        ],
+        fold_lines(&lines),
+    )
+        .into_response())
+}
+
+/// `/calendar/{token}.ics`, every event the token's owner is going to, for
+/// calendar apps to subscribe to. The token is the only credential.
+pub async fn calendar_feed(
+    State(app_state): State<AppState>,
+    Path(feed): Path<String>,
+) -> Result<Response, AppError> {
+    let Some(token) = feed.strip_suffix(".ics") else {
+        return Err(AppError::NotFound);
+    };
+
+    let Some(user) = User::find()
+        .filter(users::Column::CalendarToken.eq(token))
+        .one(&app_state.db_connection)
+        .await?
+    else {
+        return Err(AppError::NotFound);
+    };
+
+    let going_to = SqlQuery::select()
+        .column(user_events::Column::EventId)
+        .from(user_events::Entity)
+        .and_where(user_events::Column::UserId.eq(user.id))
+        .to_owned();
+    let events = Event::find()
+        .filter(events::Column::Id.in_subquery(going_to))
+        .filter(events::Column::HiddenAt.is_null())
+        .order_by_asc(events::Column::Date)
+        .all(&app_state.db_connection)
+        .await?;
+
+    let mut lines = calendar_header(&format!("Happening nu - {}", user.username));
+    for event in &events {
+        lines.extend(vevent(event, &app_state.base_url));
+    }
+    lines.push(String::from("END:VCALENDAR"));
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        fold_lines(&lines),
+    )
+        .into_response())
+}
+
+/// Replaces the user's calendar token, so an old subscription URL that leaked
+/// stops working.
+pub async fn reset_calendar_token(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    let Some(user) = User::find_by_id(uid)
+        .one(&app_state.db_connection)
+        .await?
+    else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let mut active_model: users::ActiveModel = user.into();
+    active_model.calendar_token = Set(Some(random_token()));
+    let _ = active_model.update(&app_state.db_connection).await?;
+
+    messages.info("Your calendar link has been replaced. Update it in your calendar app.");
+    Ok(Redirect::to("/agenda").into_response())
+}
+
+/// Returns the user's calendar subscription token, creating it on first use.
+pub async fn calendar_token_for(db: &DatabaseConnection, user_id: i32) -> Result<Option<String>, AppError> {
+    let Some(user) = User::find_by_id(user_id).one(db).await? else {
+        return Ok(None);
+    };
+    if let Some(token) = user.calendar_token.clone() {
+        return Ok(Some(token));
+    }
+
+    let token = random_token();
+    let mut active_model: users::ActiveModel = user.into();
+    active_model.calendar_token = Set(Some(token.clone()));
+    let _ = active_model.update(db).await?;
+    Ok(Some(token))
+}
+
+fn calendar_header(name: &str) -> Vec<String> {
+    vec![
+        String::from("BEGIN:VCALENDAR"),
+        String::from("VERSION:2.0"),
+        String::from("PRODID:-//Happening nu//Events//EN"),
+        String::from("CALSCALE:GREGORIAN"),
+        String::from("METHOD:PUBLISH"),
+        format!("X-WR-CALNAME:{}", escape_text(name)),
+    ]
+}
+
+/// One RFC 5545 VEVENT. Events have a date but no time, so they are all-day
+/// events ending (exclusively) the day after.
+fn vevent(event: &events::Model, base_url: &str) -> Vec<String> {
+    let mut lines = vec![
+        String::from("BEGIN:VEVENT"),
+        format!("UID:event-{}@happeningnu", event.id),
+        format!("DTSTAMP:{}", format_utc(Utc::now().naive_utc())),
+        format!("CREATED:{}", format_utc(event.created_at)),
+        format!("LAST-MODIFIED:{}", format_utc(event.updated_at.unwrap_or(event.created_at))),
+    ];
+    if let Ok(date) = NaiveDate::parse_from_str(&event.date, "%Y-%m-%d") {
+        lines.push(format!("DTSTART;VALUE=DATE:{}", date.format("%Y%m%d")));
+        lines.push(format!("DTEND;VALUE=DATE:{}", (date + Duration::days(1)).format("%Y%m%d")));
+    }
+
+    let event_page = format!("{}/event/{}", base_url, event.id);
+    let description = if event.description.is_empty() {
+        event_page
+    } else {
+        format!("{}\n\n{}", event.description, event_page)
+    };
+    lines.push(format!("SUMMARY:{}", escape_text(&event.title)));
+    lines.push(format!("LOCATION:{}", escape_text(&event.location)));
+    lines.push(format!("CATEGORIES:{}", escape_text(&event.category)));
+    lines.push(format!("DESCRIPTION:{}", escape_text(&description)));
+    lines.push(format!("URL:{}", event.url));
+    lines.push(String::from("END:VEVENT"));
+    lines
+}
+
+fn format_utc(timestamp: NaiveDateTime) -> String {
+    timestamp.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escapes a TEXT value (RFC 5545 section 3.3.11).
+fn escape_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Joins content lines with CRLF, folding any longer than 75 octets onto
+/// continuation lines that start with a space (RFC 5545 section 3.1).
+fn fold_lines(lines: &[String]) -> String {
+    let mut output = String::new();
+    for line in lines {
+        let mut octets = 0;
+        for ch in line.chars() {
+            if octets + ch.len_utf8() > 75 {
+                output.push_str("\r\n ");
+                octets = 1;
+            }
+            output.push(ch);
+            octets += ch.len_utf8();
+        }
+        output.push_str("\r\n");
+    }
+    output
+}
@@ -0,0 +1,95 @@
+// External crates
+use axum::{
+    routing::get,
+    extract::State,
+    response::Html,
+    Json,
+    Router,
+};
+use axum_messages::Messages;
+use serde_json::{json, Value};
+use tera::Context;
+use tower_sessions::Session;
+
+// Internal modules
+use crate::AppState;
+use crate::csrf::csrf_token;
+use crate::error::AppError;
+use crate::helper::get_username_from_session;
+use crate::openapi::{operations, spec};
+
+pub fn docs_router() -> Router<AppState> {
+    Router::new()
+    .route("/api/openapi.json", get(openapi_json))
+    .route("/api/docs", get(api_docs))
+}
+
+pub async fn openapi_json(State(app_state): State<AppState>) -> Json<Value> {
+    Json(spec(&app_state.base_url))
+}
+
+/// The OpenAPI document as a page, grouped by tag in the order routes are
+/// documented, with schema references resolved so the template can show fields.
+pub async fn api_docs(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Html<String>, AppError> {
+    let tera = &app_state.tera;
+    let mut context = Context::new();
+
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+
+    let is_logged_in = session.get::<i32>("user_id").await.unwrap_or(None).is_some();
+    context.insert("is_logged_in", &is_logged_in);
+    let logged_in_username = get_username_from_session(&session, &app_state.db_connection).await?;
+    context.insert("logged_in_username", &logged_in_username);
+    context.insert("not_home", &true);
+
+    let document = spec(&app_state.base_url);
+    let mut sections: Vec<Value> = Vec::new();
+    for operation in operations() {
+        let mut entry = resolve_refs(&operation.to_json(), &document);
+        entry["method"] = json!(operation.method.to_uppercase());
+        entry["path"] = json!(operation.path);
+        let tag = entry["tags"][0].clone();
+        match sections.iter_mut().find(|section| section["tag"] == tag) {
+            Some(section) => {
+                if let Some(list) = section["operations"].as_array_mut() {
+                    list.push(entry);
+                }
+            }
+            None => sections.push(json!({ "tag": tag, "operations": [entry] })),
+        }
+    }
+
+    context.insert("info", &document["info"]);
+    context.insert("sections", &sections);
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(&session).await?);
+    context.insert("title", "API docs");
+    Ok(Html(tera.render("partials/api_docs.html", &context)?))
+}
+
+/// Replaces `{"$ref": "#/components/..."}` with what it points to.
+fn resolve_refs(value: &Value, document: &Value) -> Value {
+    match value {
+        Value::Object(fields) => {
+            if let Some(target) = fields.get("$ref").and_then(Value::as_str) {
+                let pointer = target.trim_start_matches('#');
+                return resolve_refs(document.pointer(pointer).unwrap_or(&Value::Null), document);
+            }
+            Value::Object(
+                fields
+                    .iter()
+                    .map(|(key, field)| (key.clone(), resolve_refs(field, document)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| resolve_refs(item, document)).collect()),
+        other => other.clone(),
+    }
+}
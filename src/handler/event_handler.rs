@@ -2,16 +2,19 @@
 use axum::{
     routing::get,
     routing::post,
-    extract::{State, Path},
+    extract::{State, Path, Query},
     Router,
-    response::{Html, IntoResponse, Redirect},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
     Form,
 };
 use axum_messages::{Message, Messages};
+use chrono::{Datelike, Duration, Local, NaiveDate, Utc, Weekday};
 use serde_json;
+use std::collections::HashMap;
 use tera::Context;
 use tower_sessions::Session;
-use validator::Validate;
+use validator::ValidateArgs;
 
 use sea_orm::{
     ActiveModelTrait,
@@ -19,35 +22,163 @@ use sea_orm::{
     QueryOrder,
     ColumnTrait,
     QueryFilter,
+    QuerySelect,
     PaginatorTrait,
+    QueryTrait,
+    RelationTrait,
+    DatabaseConnection,
+    DbErr,
+    JoinType,
+    Select,
     Set,
+    sea_query::{Expr, Func, Query as SqlQuery, SimpleExpr},
 };
 
 // Internal modules
 use crate::AppState;
-use crate::handler::models::{ EVENT_LOCATIONS, EVENT_CATEGORIES, NewEventData };
+use crate::auth::Role;
+use crate::csrf::csrf_token;
+use crate::error::AppError;
+use crate::handler::calendar_handler::{ calendar_token_for, event_ics };
+use crate::handler::feed_handler::query_string;
+use crate::handler::flag_handler::has_flagged;
+use crate::handler::verification_handler::UNVERIFIED_EMAIL;
+use crate::handler::models::{ EVENTS_PER_PAGE, FLAG_REASONS, AgendaWeek, EventChoices, EventFilter, EventListItem, NewEventData, Pagination };
+use crate::entities::users;
 use crate::entities::users::Entity as User;
 use crate::entities::events;
 use crate::entities::events::Entity as Event;
 use crate::entities::user_events;
 use crate::entities::user_events::Entity as UserEvent;
-use crate::helper::get_username_from_session;
+use crate::helper::{ email_verified, event_choices, field_errors, get_role_from_session, get_username_from_session };
 
 pub fn event_router() -> Router<AppState> {
     Router::new()
     .route("/", get(all_events))
+    .route("/archive", get(archived_events))
     .route("/user_events", get(user_events))
+    .route("/agenda", get(agenda))
     .route("/new_event", get(new_event_form))
     .route("/new_event", post(process_new_event_form))
+    .route("/event/{id}", get(event_detail))
+    .route("/event/{id}/edit", get(edit_event_form))
+    .route("/event/{id}/edit", post(process_edit_event_form))
     .route("/event/{id}/delete", post(delete_event))
     .route("/api/event/{id}/going", post(mark_event_going))
 }
 
 pub async fn all_events(
     State(app_state): State<AppState>,
+    Query(filter): Query<EventFilter>,
+    Query(pagination): Query<Pagination>,
+    headers: HeaderMap,
     messages: Messages,
     session: Session,
-) -> Html<String> {
+) -> Result<Html<String>, AppError> {
+    list_events(app_state, filter, pagination, headers, messages, session, false).await
+}
+
+pub async fn archived_events(
+    State(app_state): State<AppState>,
+    Query(filter): Query<EventFilter>,
+    Query(pagination): Query<Pagination>,
+    headers: HeaderMap,
+    messages: Messages,
+    session: Session,
+) -> Result<Html<String>, AppError> {
+    list_events(app_state, filter, pagination, headers, messages, session, true).await
+}
+
+/// Events joined with their organizer and attendee count, grouped so that
+/// a listing costs one query no matter how many events it shows. `viewer` is
+/// the logged in user, used to tell which events they are already going to.
+fn event_list_query(viewer: Option<i32>) -> Select<Event> {
+    let is_going = match viewer {
+        Some(uid) => Expr::cust_with_values(
+            "MAX(CASE WHEN \"user_events\".\"user_id\" = ? THEN 1 ELSE 0 END)",
+            [uid],
+        ),
+        None => Expr::val(false).into(),
+    };
+    Event::find()
+        .select_only()
+        .columns([
+            events::Column::Id,
+            events::Column::Title,
+            events::Column::Url,
+            events::Column::Location,
+            events::Column::Date,
+            events::Column::Category,
+        ])
+        .column_as(
+            SimpleExpr::from(Func::coalesce([
+                Expr::col((users::Entity, users::Column::Username)).into(),
+                Expr::val("unknown").into(),
+            ])),
+            "username",
+        )
+        .column_as(user_events::Column::Id.count(), "attendee_count")
+        .column_as(is_going, "is_going")
+        .join(JoinType::LeftJoin, events::Relation::Users.def())
+        .join(JoinType::LeftJoin, events::Relation::UserEvents.def())
+        .group_by(events::Column::Id)
+}
+
+/// Fetches one page of the home feed or archive together with the total
+/// number of pages, in two queries regardless of page size.
+pub async fn fetch_event_page(
+    db: &DatabaseConnection,
+    filter: &EventFilter,
+    past: bool,
+    page: u64,
+    viewer: Option<i32>,
+) -> Result<(Vec<EventListItem>, u64), DbErr> {
+    // Dates are stored as YYYY-MM-DD so string comparison orders them correctly
+    let today = Local::now().date_naive().format("%Y-%m-%d").to_string();
+    let query = if past {
+        event_list_query(viewer)
+            .filter(events::Column::Date.lt(today))
+            .order_by_desc(events::Column::Date)
+            .order_by_desc(events::Column::Id)
+    } else {
+        event_list_query(viewer)
+            .filter(events::Column::Date.gte(today))
+            .order_by_asc(events::Column::Date)
+            .order_by_asc(events::Column::Id)
+    };
+
+    let paginator = query
+        .filter(events::Column::HiddenAt.is_null())
+        .apply_if(filter.location.clone(), |query, location| {
+            query.filter(events::Column::Location.eq(location))
+        })
+        .apply_if(filter.category.clone(), |query, category| {
+            query.filter(events::Column::Category.eq(category))
+        })
+        .apply_if(filter.from.clone(), |query, from| {
+            query.filter(events::Column::Date.gte(from))
+        })
+        .apply_if(filter.to.clone(), |query, to| {
+            query.filter(events::Column::Date.lte(to))
+        })
+        .into_model::<EventListItem>()
+        .paginate(db, EVENTS_PER_PAGE);
+    let num_pages = paginator.num_pages().await?;
+    let events = paginator.fetch_page(page.max(1) - 1).await?;
+    Ok((events, num_pages))
+}
+
+/// Shared by the home feed and the archive: upcoming events soonest first,
+/// or past events newest first when `past` is set.
+async fn list_events(
+    app_state: AppState,
+    mut filter: EventFilter,
+    pagination: Pagination,
+    headers: HeaderMap,
+    messages: Messages,
+    session: Session,
+    past: bool,
+) -> Result<Html<String>, AppError> {
     let tera = &app_state.tera;
     let mut context = Context::new();
 
@@ -56,55 +187,55 @@ pub async fn all_events(
         info_to_user.push(msg.message);
     }
 
-    let is_logged_in = session.get::<i32>("user_id").await.unwrap_or(None).is_some();
-    context.insert("is_logged_in", &is_logged_in);
-    let logged_in_username = get_username_from_session(&session, &app_state.db_connection).await;
+    // Old events may be in a retired city or category, so those still filter
+    let choices = event_choices(&app_state.db_connection, true).await?;
+    if filter.validate_with_args(&choices).is_err() {
+        // Unknown city, category or a malformed date, show everything instead
+        info_to_user.push(String::from("Invalid filter, showing all events."));
+        filter = EventFilter::default();
+    }
+
+    let user_id = session.get::<i32>("user_id").await.unwrap_or(None);
+    context.insert("is_logged_in", &user_id.is_some());
+    let logged_in_username = get_username_from_session(&session, &app_state.db_connection).await?;
     context.insert("logged_in_username", &logged_in_username);
 
-    let events = Event::find()
-        .order_by_asc(events::Column::Date)
-        .all(&app_state.db_connection)
-        .await
-        .unwrap();
-    let mut events_with_count = Vec::new();
-    for event in &events {
-        // Query the username of the event creator
-        let username = if let Some(user) = User::find_by_id(event.user_id)
-            .one(&app_state.db_connection)
-            .await
-            .unwrap()
-        {
-            user.username.clone()
-        } else {
-            String::from("unknown")
-        };
-        let count = UserEvent::find()
-            .filter(user_events::Column::EventId.eq(event.id))
-            .count(&app_state.db_connection)
-            .await
-            .unwrap();
-        events_with_count.push(serde_json::json!({
-            "id": event.id,
-            "title": event.title,
-            "url": event.url,
-            "location": event.location,
-            "date": event.date,
-            "category": event.category,
-            "attendee_count": count,
-            "username": username
-        }));
+    // Pages are 1-based in the URL, like ?page=2 on Hacker News
+    let page = pagination.page.max(1);
+    let (events, num_pages) = fetch_event_page(&app_state.db_connection, &filter, past, page, user_id)
+        .await?;
+    context.insert("all_events", &events);
+    context.insert("filter", &filter);
+    context.insert("list_url", if past { "/archive" } else { "/" });
+    if page < num_pages {
+        context.insert("next_page", &(page + 1));
+    }
+
+    // htmx "More" requests only need the next rows, not the whole page
+    if headers.contains_key("HX-Request") {
+        return Ok(Html(tera.render("partials/event_rows.html", &context)?));
     }
-    context.insert("all_events", &events_with_count);
+
+    context.insert("feed_query", &query_string(&filter));
+    context.insert("event_categories", &choices.categories);
+    context.insert("event_locations", &choices.locations);
     context.insert("messages", &info_to_user);
-    context.insert("title", "Happening nu");
-    Html(tera.render("partials/home.html", &context).unwrap())
+    context.insert("csrf_token", &csrf_token(&session).await?);
+    if past {
+        context.insert("title", "Archive");
+        context.insert("not_home", &true);
+        context.insert("is_archive", &true);
+    } else {
+        context.insert("title", "Happening nu");
+    }
+    Ok(Html(tera.render("partials/home.html", &context)?))
 }
 
 pub async fn user_events(
     State(app_state): State<AppState>,
     messages: Messages,
     session: Session,
-) -> Html<String> {
+) -> Result<Html<String>, AppError> {
     let tera = &app_state.tera;
     let mut context = Context::new();
 
@@ -115,7 +246,7 @@ pub async fn user_events(
 
     let is_logged_in = session.get::<i32>("user_id").await.unwrap_or(None).is_some();
     context.insert("is_logged_in", &is_logged_in);
-    let logged_in_username = get_username_from_session(&session, &app_state.db_connection).await;
+    let logged_in_username = get_username_from_session(&session, &app_state.db_connection).await?;
     context.insert("logged_in_username", &logged_in_username);
     context.insert("not_home", &true);
 
@@ -125,61 +256,201 @@ pub async fn user_events(
 
     if let Some(uid) = user_id {
         // Find all events created by this user
-        let user_events = Event::find()
+        events_with_count = event_list_query(Some(uid))
             .filter(events::Column::UserId.eq(uid))
             .order_by_asc(events::Column::Date)
+            .into_model::<EventListItem>()
             .all(&app_state.db_connection)
-            .await
-            .unwrap();
-
-        for event in &user_events {
-            let count = UserEvent::find()
-                .filter(user_events::Column::EventId.eq(event.id))
-                .count(&app_state.db_connection)
-                .await
-                .unwrap();
-            events_with_count.push(serde_json::json!({
-                "id": event.id,
-                "title": event.title,
-                "url": event.url,
-                "location": event.location,
-                "date": event.date,
-                "category": event.category,
-                "attendee_count": count
-            }));
-        }
+            .await?;
     }
     context.insert("user_events", &events_with_count);
     context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(&session).await?);
     context.insert("title", "Happening nu");
-    Html(tera.render("partials/user_events.html", &context).unwrap())
+    Ok(Html(tera.render("partials/user_events.html", &context)?))
 }
 
-pub async fn new_event_form(
+pub async fn agenda(
     State(app_state): State<AppState>,
     messages: Messages,
     session: Session,
-) -> impl IntoResponse {
-    if session.get::<i32>("user_id").await.unwrap_or(None).is_none() {
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
         // Not logged in, redirect to login page
-        return Redirect::to("/login").into_response();
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let tera = &app_state.tera;
+    let mut context = Context::new();
+
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+
+    context.insert("is_logged_in", &true);
+    let logged_in_username = get_username_from_session(&session, &app_state.db_connection).await?;
+    context.insert("logged_in_username", &logged_in_username);
+    context.insert("not_home", &true);
+
+    // Upcoming events this user has RSVP'd to, soonest first
+    let today = Local::now().date_naive();
+    let going_to = SqlQuery::select()
+        .column(user_events::Column::EventId)
+        .from(user_events::Entity)
+        .and_where(user_events::Column::UserId.eq(uid))
+        .to_owned();
+    let events = event_list_query(Some(uid))
+        .filter(events::Column::Id.in_subquery(going_to))
+        .filter(events::Column::HiddenAt.is_null())
+        .filter(events::Column::Date.gte(today.format("%Y-%m-%d").to_string()))
+        .order_by_asc(events::Column::Date)
+        .order_by_asc(events::Column::Id)
+        .into_model::<EventListItem>()
+        .all(&app_state.db_connection)
+        .await?;
+
+    let calendar_token = calendar_token_for(&app_state.db_connection, uid).await?;
+    context.insert("calendar_url", &calendar_token.map(|token| format!("{}/calendar/{}.ics", app_state.base_url, token)));
+    context.insert("weeks", &group_by_week(events, today));
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(&session).await?);
+    context.insert("title", "My agenda");
+    Ok(Html(tera.render("partials/agenda.html", &context)?).into_response())
+}
+
+/// Buckets events, already sorted by date, into Monday to Sunday weeks.
+fn group_by_week(events: Vec<EventListItem>, today: NaiveDate) -> Vec<AgendaWeek> {
+    let this_monday = today.week(Weekday::Mon).first_day();
+    let mut weeks: Vec<AgendaWeek> = Vec::new();
+    for event in events {
+        let date = NaiveDate::parse_from_str(&event.date, "%Y-%m-%d").unwrap_or(today);
+        let monday = date.week(Weekday::Mon).first_day();
+        let label = match (monday - this_monday).num_weeks() {
+            0 => String::from("This week"),
+            1 => String::from("Next week"),
+            _ => format!(
+                "Week {}, {} to {}",
+                monday.iso_week().week(),
+                monday.format("%b %-d"),
+                (monday + Duration::days(6)).format("%b %-d"),
+            ),
+        };
+        match weeks.last_mut() {
+            Some(week) if week.label == label => week.events.push(event),
+            _ => weeks.push(AgendaWeek { label, events: vec![event] }),
+        }
     }
+    weeks
+}
+
+pub async fn event_detail(
+    State(app_state): State<AppState>,
+    Path(event_path): Path<String>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    // `/event/{id}.ics` shares this route, see `calendar_handler::event_ics`
+    if let Some(event_id) = event_path.strip_suffix(".ics") {
+        let event_id = event_id.parse::<i32>().map_err(|_| AppError::NotFound)?;
+        return event_ics(&app_state, event_id).await;
+    }
+    let event_id = event_path.parse::<i32>().map_err(|_| AppError::NotFound)?;
 
     let tera = &app_state.tera;
     let mut context = Context::new();
-    let mut info_to_user: Vec<Message> = vec![];
+
+    let Some(event) = Event::find_by_id(event_id)
+        .one(&app_state.db_connection)
+        .await?
+    else {
+        return Err(AppError::NotFound);
+    };
+
+    let mut info_to_user: Vec<String> = vec![];
     for msg in messages.into_iter() {
-        info_to_user.push(msg);
+        info_to_user.push(msg.message);
     }
-    let is_logged_in = session.get::<i32>("user_id").await.unwrap_or(None).is_some();
-    context.insert("is_logged_in", &is_logged_in);
-    let logged_in_username = get_username_from_session(&session, &app_state.db_connection).await;
+
+    let user_id = session.get::<i32>("user_id").await.unwrap_or(None);
+    let is_owner = user_id == Some(event.user_id);
+    let is_staff = get_role_from_session(&session, &app_state.db_connection).await? >= Role::Moderator;
+    // Hidden events are only there for their owner and the moderators
+    if event.hidden_at.is_some() && !is_owner && !is_staff {
+        return Err(AppError::NotFound);
+    }
+    context.insert("is_logged_in", &user_id.is_some());
+    let logged_in_username = get_username_from_session(&session, &app_state.db_connection).await?;
     context.insert("logged_in_username", &logged_in_username);
+    context.insert("not_home", &true);
+    context.insert("is_owner", &is_owner);
+    context.insert("is_staff", &is_staff);
+
+    // Query the username of the event creator
+    let organizer = if let Some(user) = User::find_by_id(event.user_id)
+        .one(&app_state.db_connection)
+        .await?
+    {
+        user.username.clone()
+    } else {
+        String::from("unknown")
+    };
+
+    // Users who marked themselves as going
+    let attendee_rows = UserEvent::find()
+        .filter(user_events::Column::EventId.eq(event.id))
+        .find_also_related(User)
+        .order_by_asc(user_events::Column::Id)
+        .all(&app_state.db_connection)
+        .await?;
+    let is_going = attendee_rows.iter().any(|(rsvp, _)| Some(rsvp.user_id) == user_id);
+    let attendees: Vec<String> = attendee_rows
+        .into_iter()
+        .filter_map(|(_, user)| user.map(|u| u.username))
+        .collect();
+
+    context.insert("event", &serde_json::json!({
+        "id": event.id,
+        "title": event.title,
+        "url": event.url,
+        "location": event.location,
+        "date": event.date,
+        "category": event.category,
+        "description": event.description,
+        "created_at": event.created_at.format("%Y-%m-%d %H:%M").to_string(),
+        "updated_at": event.updated_at.map(|t| t.format("%Y-%m-%d %H:%M").to_string()),
+        "username": organizer,
+        "attendee_count": attendees.len(),
+        "is_going": is_going,
+        "hidden": event.hidden_at.is_some(),
+    }));
+    context.insert("attendees", &attendees);
+    if let Some(uid) = user_id.filter(|_| !is_owner) {
+        context.insert("flag_reasons", &FLAG_REASONS);
+        context.insert("has_flagged", &has_flagged(&app_state.db_connection, event.id, uid).await?);
+    }
     context.insert("messages", &info_to_user);
-    context.insert("title", "New event");
-    context.insert("event_categories", &EVENT_CATEGORIES);
-    context.insert("event_locations", &EVENT_LOCATIONS);
-    Html(tera.render("partials/new_event.html", &context).unwrap()).into_response()
+    context.insert("csrf_token", &csrf_token(&session).await?);
+    context.insert("title", &event.title);
+    Ok(Html(tera.render("partials/event.html", &context)?).into_response())
+}
+
+pub async fn new_event_form(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        // Not logged in, redirect to login page
+        return Ok(Redirect::to("/login").into_response());
+    };
+    if !email_verified(&app_state.db_connection, uid).await? {
+        messages.error(UNVERIFIED_EMAIL);
+        return Ok(Redirect::to("/settings").into_response());
+    }
+
+    let choices = event_choices(&app_state.db_connection, false).await?;
+    render_event_form(&app_state, &session, messages, &NewEventData::default(), &choices, &HashMap::new(), None).await
 }
 
 pub async fn process_new_event_form(
@@ -187,104 +458,343 @@ pub async fn process_new_event_form(
     messages: Messages,
     session: Session,
     Form(data): Form<NewEventData>,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
+    let user_id = session.get::<i32>("user_id").await.unwrap_or(None);
+    let Some(uid) = user_id else {
+        // Not logged in, redirect
+        return Ok(Redirect::to("/login").into_response());
+    };
+    if !email_verified(&app_state.db_connection, uid).await? {
+        messages.error(UNVERIFIED_EMAIL);
+        return Ok(Redirect::to("/settings").into_response());
+    }
+
+    let choices = event_choices(&app_state.db_connection, false).await?;
+    if let Err(errors) = data.validate_with_args(&choices) {
+        // Show the form again with what was typed and a message per field
+        return render_event_form(&app_state, &session, messages, &data, &choices, &field_errors(&errors), None).await;
+    }
+
+    let new_event = events::ActiveModel {
+        title: Set(data.title.clone()),
+        url: Set(data.url.clone()),
+        location: Set(data.location.clone()),
+        date: Set(data.date.clone()),
+        category: Set(data.category.clone()),
+        description: Set(data.description.clone()),
+        user_id: Set(uid),
+        ..Default::default()
+    };
+    let _ = new_event.insert(&app_state.db_connection).await?;
+    Ok(Redirect::to("/").into_response())
+}
+
+pub async fn edit_event_form(
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        // Not logged in, redirect to login page
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let Some(event) = Event::find_by_id(event_id)
+        .one(&app_state.db_connection)
+        .await?
+    else {
+        return Err(AppError::NotFound);
+    };
+
+    if event.user_id != uid {
+        messages.error("You can only edit your own events.");
+        return Ok(Redirect::to(&format!("/event/{}", event_id)).into_response());
+    }
+
+    let choices = event_choices(&app_state.db_connection, false)
+        .await?
+        .allow(&event.location, &event.category);
+    let form = NewEventData {
+        title: event.title,
+        url: event.url,
+        location: event.location,
+        date: event.date,
+        category: event.category,
+        description: event.description,
+    };
+    render_event_form(&app_state, &session, messages, &form, &choices, &HashMap::new(), Some(event_id)).await
+}
+
+pub async fn process_edit_event_form(
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    messages: Messages,
+    session: Session,
+    Form(data): Form<NewEventData>,
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        // Not logged in, redirect
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let Some(event) = Event::find_by_id(event_id)
+        .one(&app_state.db_connection)
+        .await?
+    else {
+        return Err(AppError::NotFound);
+    };
+
+    // Only the creator of the event may change it
+    if event.user_id != uid {
+        messages.error("You can only edit your own events.");
+        return Ok(Redirect::to(&format!("/event/{}", event_id)).into_response());
+    }
+
+    let choices = event_choices(&app_state.db_connection, false)
+        .await?
+        .allow(&event.location, &event.category);
+    if let Err(errors) = data.validate_with_args(&choices) {
+        return render_event_form(&app_state, &session, messages, &data, &choices, &field_errors(&errors), Some(event_id)).await;
+    }
+
+    let mut active_model: events::ActiveModel = event.into();
+    active_model.title = Set(data.title.clone());
+    active_model.url = Set(data.url.clone());
+    active_model.location = Set(data.location.clone());
+    active_model.date = Set(data.date.clone());
+    active_model.category = Set(data.category.clone());
+    active_model.description = Set(data.description.clone());
+    active_model.updated_at = Set(Some(Utc::now().naive_utc()));
+    let _ = active_model.update(&app_state.db_connection).await?;
+
+    messages.info("Event updated.");
+    Ok(Redirect::to(&format!("/event/{}", event_id)).into_response())
+}
+
+/// Renders the event form for creating (`event_id` is `None`) or editing an
+/// event. When `errors` is not empty the form comes back as 422 with the
+/// submitted values filled in.
+async fn render_event_form(
+    app_state: &AppState,
+    session: &Session,
+    messages: Messages,
+    form: &NewEventData,
+    choices: &EventChoices,
+    errors: &HashMap<String, Vec<String>>,
+    event_id: Option<i32>,
+) -> Result<Response, AppError> {
+    let tera = &app_state.tera;
     let mut context = Context::new();
-    if let Err(errors) = data.validate() {
-        println!("{:?}", errors);
+    let mut info_to_user: Vec<Message> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg);
+    }
+    context.insert("is_logged_in", &true);
+    let logged_in_username = get_username_from_session(session, &app_state.db_connection).await?;
+    context.insert("logged_in_username", &logged_in_username);
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(session).await?);
+    if let Some(id) = event_id {
+        context.insert("title", "Edit event");
+        context.insert("form_action", &format!("/event/{}/edit", id));
+        context.insert("submit_label", "save");
+    } else {
         context.insert("title", "New event");
-        messages.error(format!("{:?}", errors));
-        return Redirect::to("/new_event").into_response();
+        context.insert("form_action", "/new_event");
+        context.insert("submit_label", "create");
+    }
+    context.insert("form", form);
+    context.insert("errors", errors);
+    context.insert("event_categories", &choices.categories);
+    context.insert("event_locations", &choices.locations);
+    let html = Html(tera.render("partials/new_event.html", &context)?);
+    if errors.is_empty() {
+        Ok(html.into_response())
     } else {
-        let user_id = session.get::<i32>("user_id").await.unwrap_or(None);
-        if let Some(uid) = user_id {
-            let new_event = events::ActiveModel {
-                title: Set(data.title.clone()),
-                url: Set(data.url.clone()),
-                location: Set(data.location.clone()),
-                date: Set(data.date.clone()),
-                category: Set(data.category.clone()),
-                user_id: Set(uid), // <-- Add this line
-                ..Default::default()
-            };
-            let _ = new_event.insert(&app_state.db_connection).await.unwrap();
-        } else {
-            // Not logged in, redirect
-            return Redirect::to("/login").into_response();
-        }
-    };
-    Redirect::to("/").into_response()
+        Ok((StatusCode::UNPROCESSABLE_ENTITY, html).into_response())
+    }
 }
 
 pub async fn delete_event(
     State(app_state): State<AppState>,
     Path(event_id): Path<i32>,
     session: Session,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
     let user_id = session.get::<i32>("user_id").await.unwrap_or(None);
 
     if let Some(uid) = user_id {
         // Find the event
         if let Some(event) = Event::find_by_id(event_id)
             .one(&app_state.db_connection)
-            .await
-            .unwrap()
+            .await?
         {
-            // Check ownership (replace `user_id` with your actual owner column)
+            // Only the creator of the event may delete it
             if event.user_id == uid {
-                // 1. Delete all user_events for this event
-                //let _ = UserEvent::delete_many()
-                //    .filter(user_events::Column::EventId.eq(event_id))
-                //    .exec(&app_state.db_connection)
-                //    .await;
-                // 2. Now delete the event
                 let active_model: events::ActiveModel = event.into();
-                let _ = active_model.delete(&app_state.db_connection).await;
-                // Return an empty string or a message to remove the row in htmx
-                Redirect::to("/user_events").into_response()
+                let _ = active_model.delete(&app_state.db_connection).await?;
+                Ok(Redirect::to("/user_events").into_response())
             } else {
-                Redirect::to("/user_events").into_response()
+                Ok(Redirect::to("/user_events").into_response())
             }
         } else {
-            Redirect::to("/user_events").into_response()
+            Err(AppError::NotFound)
         }
     } else {
-        Redirect::to("/login").into_response()
+        Ok(Redirect::to("/login").into_response())
     }
-} 
+}
 
 pub async fn mark_event_going(
     State(app_state): State<AppState>,
     Path(event_id): Path<i32>,
     session: Session,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
+    if Event::find_by_id(event_id)
+        .filter(events::Column::HiddenAt.is_null())
+        .one(&app_state.db_connection)
+        .await?
+        .is_none()
+    {
+        return Err(AppError::NotFound);
+    }
+
     let user_id = session.get::<i32>("user_id").await.unwrap_or(None);
+    let mut is_going = false;
     if let Some(uid) = user_id {
-        let user_already_going = UserEvent::find()
-            .filter(user_events::Column::UserId.eq(uid))
-            .filter(user_events::Column::EventId.eq(event_id))
-            .one(&app_state.db_connection)
-            .await
-            .unwrap();
-
-        if let Some(rec) = user_already_going {
-            let active_model: user_events::ActiveModel = rec.into();
-            let _ = active_model.delete(&app_state.db_connection).await;
-        } else {
-            let new_user_event = user_events::ActiveModel {
-                user_id: Set(uid),
-                event_id: Set(event_id),
-                ..Default::default()
-            };
-            let _ = new_user_event.insert(&app_state.db_connection).await;
-        }
+        is_going = toggle_going(&app_state.db_connection, uid, event_id).await?;
     }
     let count = UserEvent::find()
         .filter(user_events::Column::EventId.eq(event_id))
         .count(&app_state.db_connection)
+        .await?;
+
+    // Swap the button and count so the button shows the new state
+    let mut context = Context::new();
+    context.insert("is_logged_in", &user_id.is_some());
+    context.insert("event", &serde_json::json!({
+        "id": event_id,
+        "attendee_count": count,
+        "is_going": is_going,
+    }));
+    Ok(Html(app_state.tera.render("partials/going.html", &context)?).into_response())
+}
+
+/// Marks the user as going to the event, or takes the mark back if it was
+/// already there. Returns whether the user is going afterwards.
+pub async fn toggle_going(db: &DatabaseConnection, user_id: i32, event_id: i32) -> Result<bool, DbErr> {
+    let user_already_going = UserEvent::find()
+        .filter(user_events::Column::UserId.eq(user_id))
+        .filter(user_events::Column::EventId.eq(event_id))
+        .one(db)
+        .await?;
+
+    if let Some(rec) = user_already_going {
+        let active_model: user_events::ActiveModel = rec.into();
+        let _ = active_model.delete(db).await?;
+        Ok(false)
+    } else {
+        let new_user_event = user_events::ActiveModel {
+            user_id: Set(user_id),
+            event_id: Set(event_id),
+            ..Default::default()
+        };
+        let _ = new_user_event.insert(db).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use chrono::{Duration, Local};
+    use sea_orm::{ActiveModelTrait, ConnectionTrait, Database, DatabaseConnection, Schema, Set};
+
+    use super::fetch_event_page;
+    use crate::entities::{events, user_events, users};
+    use crate::handler::models::EventFilter;
+
+    async fn setup_db() -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(db.get_database_backend());
+        for statement in [
+            schema.create_table_from_entity(users::Entity),
+            schema.create_table_from_entity(events::Entity),
+            schema.create_table_from_entity(user_events::Entity),
+        ] {
+            db.execute(db.get_database_backend().build(&statement)).await.unwrap();
+        }
+        users::ActiveModel {
+            username: Set(String::from("alice")),
+            email: Set(String::from("alice@example.com")),
+            password: Set(String::new()),
+            joined_at: Set(Local::now().naive_utc()),
+            role: Set(String::from("user")),
+            session_version: Set(0),
+            legacy_salt: Set(false),
+            ..Default::default()
+        }
+        .insert(&db)
         .await
         .unwrap();
-    Html(format!(
-        r#"<span style="color: #828282; font-size: 0.75em;" id="attendee-count-{}">Going: {}</span>"#,
-        event_id, count
-    )).into_response()
-}
\ No newline at end of file
+        db
+    }
+
+    async fn add_events(db: &DatabaseConnection, count: usize) {
+        let tomorrow = (Local::now() + Duration::days(1)).format("%Y-%m-%d").to_string();
+        for i in 0..count {
+            let event = events::ActiveModel {
+                title: Set(format!("Event {}", i)),
+                location: Set(String::from("Lund")),
+                created_at: Set(Local::now().naive_utc()),
+                category: Set(String::from("Social")),
+                date: Set(tomorrow.clone()),
+                url: Set(String::from("https://example.com")),
+                user_id: Set(1),
+                description: Set(String::new()),
+                ..Default::default()
+            }
+            .insert(db)
+            .await
+            .unwrap();
+            user_events::ActiveModel {
+                user_id: Set(1),
+                event_id: Set(event.id),
+                ..Default::default()
+            }
+            .insert(db)
+            .await
+            .unwrap();
+        }
+    }
+
+    /// Runs the home feed query and returns how many statements it issued.
+    async fn count_feed_queries(db: &mut DatabaseConnection) -> usize {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        db.set_metric_callback(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let (events, _) = fetch_event_page(db, &EventFilter::default(), false, 1, Some(1)).await.unwrap();
+        db.set_metric_callback(|_| {});
+        assert!(events.iter().all(|e| e.attendee_count == 1 && e.is_going && e.username == "alice"));
+        queries.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn feed_query_count_does_not_grow_with_events() {
+        let mut db = setup_db().await;
+
+        add_events(&db, 2).await;
+        let few = count_feed_queries(&mut db).await;
+
+        add_events(&db, 20).await;
+        let many = count_feed_queries(&mut db).await;
+
+        assert_eq!(few, many);
+        // One COUNT for the paginator and one SELECT for the page itself
+        assert_eq!(many, 2);
+    }
+}
@@ -0,0 +1,211 @@
+// External crates
+use axum::{
+    routing::get,
+    extract::{State, Query},
+    http::header,
+    response::{IntoResponse, Response},
+    Router,
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sea_orm::{
+    ColumnTrait,
+    EntityTrait,
+    QueryFilter,
+    QueryOrder,
+    QuerySelect,
+    QueryTrait,
+};
+use validator::ValidateArgs;
+
+// Internal modules
+use crate::AppState;
+use crate::error::AppError;
+use crate::handler::models::{ EventChoices, EventFilter };
+use crate::entities::events;
+use crate::entities::events::Entity as Event;
+use crate::entities::users::Entity as User;
+use crate::helper::event_choices;
+
+/// How many of the newest events a feed carries.
+const FEED_LENGTH: u64 = 50;
+
+pub fn feed_router() -> Router<AppState> {
+    Router::new()
+    .route("/feed.rss", get(rss_feed))
+    .route("/feed.atom", get(atom_feed))
+}
+
+/// A feed entry, the event plus what every entry needs from outside it.
+struct FeedEntry {
+    event: events::Model,
+    organizer: String,
+    link: String,
+}
+
+impl FeedEntry {
+    fn summary(&self) -> String {
+        format!("{} in {}, {}", self.event.date, self.event.location, self.event.category)
+    }
+
+    fn content(&self) -> String {
+        if self.event.description.is_empty() {
+            self.summary()
+        } else {
+            format!("{}\n\n{}", self.summary(), self.event.description)
+        }
+    }
+
+    fn updated(&self) -> NaiveDateTime {
+        self.event.updated_at.unwrap_or(self.event.created_at)
+    }
+}
+
+pub async fn rss_feed(
+    State(app_state): State<AppState>,
+    Query(filter): Query<EventFilter>,
+) -> Result<Response, AppError> {
+    let choices = event_choices(&app_state.db_connection, true).await?;
+    let filter = feed_filter(filter, &choices);
+    let entries = newest_events(&app_state, &filter).await?;
+    let feed_url = format!("{}/feed.rss{}", app_state.base_url, query_string(&filter));
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n");
+    xml.push_str(&format!("<title>{}</title>\n", escape_xml(&feed_title(&filter))));
+    xml.push_str(&format!("<link>{}/</link>\n", escape_xml(&app_state.base_url)));
+    xml.push_str(&format!("<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n", escape_xml(&feed_url)));
+    xml.push_str("<description>New events on Happening nu</description>\n");
+    if let Some(entry) = entries.first() {
+        xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>\n", utc(entry.event.created_at).to_rfc2822()));
+    }
+    for entry in &entries {
+        xml.push_str("<item>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape_xml(&entry.event.title)));
+        xml.push_str(&format!("<link>{}</link>\n", escape_xml(&entry.link)));
+        xml.push_str(&format!("<guid isPermaLink=\"true\">{}</guid>\n", escape_xml(&entry.link)));
+        xml.push_str(&format!("<description>{}</description>\n", escape_xml(&entry.content())));
+        xml.push_str(&format!("<category>{}</category>\n", escape_xml(&entry.event.category)));
+        xml.push_str(&format!("<pubDate>{}</pubDate>\n", utc(entry.event.created_at).to_rfc2822()));
+        xml.push_str("</item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+
+    Ok(([(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")], xml).into_response())
+}
+
+pub async fn atom_feed(
+    State(app_state): State<AppState>,
+    Query(filter): Query<EventFilter>,
+) -> Result<Response, AppError> {
+    let choices = event_choices(&app_state.db_connection, true).await?;
+    let filter = feed_filter(filter, &choices);
+    let entries = newest_events(&app_state, &filter).await?;
+    let feed_url = format!("{}/feed.atom{}", app_state.base_url, query_string(&filter));
+    let updated = entries
+        .iter()
+        .map(FeedEntry::updated)
+        .max()
+        .unwrap_or_else(|| Utc::now().naive_utc());
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("<id>{}</id>\n", escape_xml(&feed_url)));
+    xml.push_str(&format!("<title>{}</title>\n", escape_xml(&feed_title(&filter))));
+    xml.push_str(&format!("<updated>{}</updated>\n", utc(updated).to_rfc3339()));
+    xml.push_str(&format!("<link rel=\"self\" href=\"{}\"/>\n", escape_xml(&feed_url)));
+    xml.push_str(&format!("<link rel=\"alternate\" href=\"{}/\"/>\n", escape_xml(&app_state.base_url)));
+    for entry in &entries {
+        xml.push_str("<entry>\n");
+        xml.push_str(&format!("<id>{}</id>\n", escape_xml(&entry.link)));
+        xml.push_str(&format!("<title>{}</title>\n", escape_xml(&entry.event.title)));
+        xml.push_str(&format!("<link rel=\"alternate\" href=\"{}\"/>\n", escape_xml(&entry.link)));
+        xml.push_str(&format!("<published>{}</published>\n", utc(entry.event.created_at).to_rfc3339()));
+        xml.push_str(&format!("<updated>{}</updated>\n", utc(entry.updated()).to_rfc3339()));
+        xml.push_str(&format!("<author><name>{}</name></author>\n", escape_xml(&entry.organizer)));
+        xml.push_str(&format!("<category term=\"{}\"/>\n", escape_xml(&entry.event.category)));
+        xml.push_str(&format!("<summary>{}</summary>\n", escape_xml(&entry.summary())));
+        xml.push_str(&format!("<content type=\"text\">{}</content>\n", escape_xml(&entry.content())));
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+
+    Ok(([(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")], xml).into_response())
+}
+
+/// Feeds filter on city and category like the home page. Dates are ignored,
+/// a feed is about what's new rather than when it happens.
+fn feed_filter(filter: EventFilter, choices: &EventChoices) -> EventFilter {
+    if filter.validate_with_args(choices).is_err() {
+        return EventFilter::default();
+    }
+    EventFilter {
+        location: filter.location,
+        category: filter.category,
+        ..Default::default()
+    }
+}
+
+/// The newest events first, by when they were posted.
+async fn newest_events(app_state: &AppState, filter: &EventFilter) -> Result<Vec<FeedEntry>, AppError> {
+    let rows = Event::find()
+        .filter(events::Column::HiddenAt.is_null())
+        .apply_if(filter.location.clone(), |query, location| {
+            query.filter(events::Column::Location.eq(location))
+        })
+        .apply_if(filter.category.clone(), |query, category| {
+            query.filter(events::Column::Category.eq(category))
+        })
+        .order_by_desc(events::Column::CreatedAt)
+        .order_by_desc(events::Column::Id)
+        .limit(FEED_LENGTH)
+        .find_also_related(User)
+        .all(&app_state.db_connection)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(event, user)| FeedEntry {
+            link: format!("{}/event/{}", app_state.base_url, event.id),
+            organizer: user.map(|u| u.username).unwrap_or_else(|| String::from("unknown")),
+            event,
+        })
+        .collect())
+}
+
+fn feed_title(filter: &EventFilter) -> String {
+    let parts: Vec<&str> = [filter.category.as_deref(), filter.location.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect();
+    if parts.is_empty() {
+        String::from("Happening nu")
+    } else {
+        format!("Happening nu: {}", parts.join(" in "))
+    }
+}
+
+/// The filter as `?location=..&category=..`, or nothing when unfiltered.
+pub fn query_string(filter: &EventFilter) -> String {
+    let pairs: Vec<(&str, &String)> = [("location", &filter.location), ("category", &filter.category)]
+        .into_iter()
+        .filter_map(|(name, value)| value.as_ref().map(|value| (name, value)))
+        .collect();
+    match serde_urlencoded::to_string(pairs) {
+        Ok(query) if !query.is_empty() => format!("?{}", query),
+        _ => String::new(),
+    }
+}
+
+/// Timestamps are stored as naive UTC.
+fn utc(timestamp: NaiveDateTime) -> DateTime<Utc> {
+    timestamp.and_utc()
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
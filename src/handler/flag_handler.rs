@@ -0,0 +1,247 @@
+// External crates
+use axum::{
+    routing::{get, post},
+    extract::{State, Path},
+    response::{Html, IntoResponse, Redirect, Response},
+    Router,
+    Form,
+};
+use axum_messages::Messages;
+use chrono::Utc;
+use sea_orm::{
+    sea_query::OnConflict,
+    ActiveModelTrait,
+    ColumnTrait,
+    DatabaseConnection,
+    DbErr,
+    EntityTrait,
+    PaginatorTrait,
+    QueryFilter,
+    QueryOrder,
+    Set,
+    TransactionTrait,
+};
+use tower_sessions::Session;
+use validator::Validate;
+
+// Internal modules
+use crate::AppState;
+use crate::auth::{ Moderator, RequireRole };
+use crate::error::AppError;
+use crate::handler::admin_handler::admin_context;
+use crate::handler::models::NewFlagData;
+use crate::entities::{ event_flags, events };
+use crate::entities::event_flags::Entity as EventFlag;
+use crate::entities::events::Entity as Event;
+use crate::entities::users::Entity as User;
+use crate::helper::field_errors;
+
+pub fn flag_router() -> Router<AppState> {
+    Router::new()
+    .route("/event/{id}/flag", post(flag_event))
+    .route("/admin/flags", get(flag_queue))
+    .route("/admin/flags/{id}/restore", post(restore_flagged))
+    .route("/admin/flags/{id}/remove", post(remove_flagged))
+    .route("/admin/flags/{id}/dismiss", post(dismiss_flags))
+}
+
+/// Reports an event to the moderators. Once `flag_threshold` people have
+/// flagged it, it is hidden until a moderator restores it.
+pub async fn flag_event(
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    messages: Messages,
+    session: Session,
+    Form(data): Form<NewFlagData>,
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    let db = &app_state.db_connection;
+    let Some(event) = Event::find_by_id(event_id)
+        .filter(events::Column::HiddenAt.is_null())
+        .one(db)
+        .await?
+    else {
+        return Err(AppError::NotFound);
+    };
+    let back = Redirect::to(&format!("/event/{}", event_id)).into_response();
+
+    if event.user_id == uid {
+        messages.error("You can't flag your own event.");
+        return Ok(back);
+    }
+    let data = NewFlagData { reason: data.reason, comment: data.comment.trim().to_string() };
+    if let Err(errors) = data.validate() {
+        let message: Vec<String> = field_errors(&errors).into_values().flatten().collect();
+        messages.error(message.join(" "));
+        return Ok(back);
+    }
+    // Flagging twice at once (a double click, an htmx retry) inserts one row,
+    // and the count below sees every flag committed before this one
+    let txn = db.begin().await?;
+    let new_flag = event_flags::ActiveModel {
+        event_id: Set(event_id),
+        user_id: Set(uid),
+        reason: Set(data.reason),
+        comment: Set(data.comment),
+        ..Default::default()
+    };
+    let inserted = EventFlag::insert(new_flag)
+        .on_conflict(
+            OnConflict::columns([event_flags::Column::EventId, event_flags::Column::UserId])
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(&txn)
+        .await?;
+    if inserted == 0 {
+        txn.rollback().await?;
+        messages.error("You already flagged this event.");
+        return Ok(back);
+    }
+
+    let flag_count = EventFlag::find()
+        .filter(event_flags::Column::EventId.eq(event_id))
+        .count(&txn)
+        .await?;
+    if flag_count >= app_state.flag_threshold {
+        let mut active_model: events::ActiveModel = event.into();
+        active_model.hidden_at = Set(Some(Utc::now().naive_utc()));
+        let _ = active_model.update(&txn).await?;
+    }
+    txn.commit().await?;
+    messages.info("Thanks, a moderator will take a look.");
+    Ok(back)
+}
+
+/// Whether the user has an open flag on the event.
+pub async fn has_flagged(db: &DatabaseConnection, event_id: i32, user_id: i32) -> Result<bool, DbErr> {
+    Ok(EventFlag::find()
+        .filter(event_flags::Column::EventId.eq(event_id))
+        .filter(event_flags::Column::UserId.eq(user_id))
+        .count(db)
+        .await? > 0)
+}
+
+/// Flagged events, the most flagged first, each with who flagged it and why.
+pub async fn flag_queue(
+    staff: RequireRole<Moderator>,
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let mut context = admin_context(&session, messages, &staff.user).await?;
+
+    let flags = EventFlag::find()
+        .find_also_related(User)
+        .order_by_asc(event_flags::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let mut event_ids: Vec<i32> = flags.iter().map(|(flag, _)| flag.event_id).collect();
+    event_ids.sort_unstable();
+    event_ids.dedup();
+    let flagged_events = Event::find()
+        .filter(events::Column::Id.is_in(event_ids))
+        .find_also_related(User)
+        .all(db)
+        .await?;
+
+    let mut queue: Vec<serde_json::Value> = flagged_events
+        .into_iter()
+        .map(|(event, organizer)| {
+            let reports: Vec<serde_json::Value> = flags
+                .iter()
+                .filter(|(flag, _)| flag.event_id == event.id)
+                .map(|(flag, user)| serde_json::json!({
+                    "reason": flag.reason,
+                    "comment": flag.comment,
+                    "username": user.as_ref().map(|u| u.username.clone()).unwrap_or_else(|| String::from("unknown")),
+                    "created_at": flag.created_at.format("%Y-%m-%d %H:%M").to_string(),
+                }))
+                .collect();
+            serde_json::json!({
+                "id": event.id,
+                "title": event.title,
+                "url": event.url,
+                "date": event.date,
+                "username": organizer.map(|u| u.username).unwrap_or_else(|| String::from("unknown")),
+                "hidden": event.hidden_at.is_some(),
+                "flag_count": reports.len(),
+                "flags": reports,
+            })
+        })
+        .collect();
+    queue.sort_by_key(|event| std::cmp::Reverse(event["flag_count"].as_u64().unwrap_or(0)));
+
+    context.insert("queue", &queue);
+    context.insert("flag_threshold", &app_state.flag_threshold);
+    context.insert("title", "Flagged events");
+    Ok(Html(app_state.tera.render("partials/admin_flags.html", &context)?).into_response())
+}
+
+/// The flags were wrong: show the event again and clear them.
+pub async fn restore_flagged(
+    _staff: RequireRole<Moderator>,
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    messages: Messages,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let Some(event) = Event::find_by_id(event_id).one(db).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    let title = event.title.clone();
+    let mut active_model: events::ActiveModel = event.into();
+    active_model.hidden_at = Set(None);
+    let _ = active_model.update(db).await?;
+    clear_flags(db, event_id).await?;
+    messages.info(format!("Restored \"{}\" and dismissed its flags.", title));
+    Ok(Redirect::to("/admin/flags").into_response())
+}
+
+/// The flags were right: delete the event, its flags go with it.
+pub async fn remove_flagged(
+    _staff: RequireRole<Moderator>,
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    messages: Messages,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let Some(event) = Event::find_by_id(event_id).one(db).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    let title = event.title.clone();
+    let active_model: events::ActiveModel = event.into();
+    let _ = active_model.delete(db).await?;
+    messages.info(format!("Removed \"{}\".", title));
+    Ok(Redirect::to("/admin/flags").into_response())
+}
+
+/// Clears the flags and leaves the event as it is, hidden or not.
+pub async fn dismiss_flags(
+    _staff: RequireRole<Moderator>,
+    State(app_state): State<AppState>,
+    Path(event_id): Path<i32>,
+    messages: Messages,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let Some(event) = Event::find_by_id(event_id).one(db).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    clear_flags(db, event_id).await?;
+    messages.info(format!("Dismissed the flags on \"{}\".", event.title));
+    Ok(Redirect::to("/admin/flags").into_response())
+}
+
+async fn clear_flags(db: &DatabaseConnection, event_id: i32) -> Result<(), DbErr> {
+    let _ = EventFlag::delete_many()
+        .filter(event_flags::Column::EventId.eq(event_id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
@@ -1,8 +1,9 @@
-use serde::{ Serialize, Deserialize };
+use sea_orm::FromQueryResult;
+use serde::{ Serialize, Deserialize, Deserializer };
 use validator::{Validate, ValidationError};
 use chrono::NaiveDate;
 
-#[derive(Serialize, Deserialize, Debug, Validate)]
+#[derive(Serialize, Deserialize, Debug, Default, Validate)]
 pub struct SignupData {
     #[validate(email(message="Email not valid."))]
     pub email: String,
@@ -13,7 +14,7 @@ pub struct SignupData {
     ))]
     pub username: String,
     #[validate(length(
-        min=4,
+        min=8,
         max=15,
         message="password should be between 8 to 15 characters."
     ))]
@@ -28,7 +29,8 @@ pub struct LoginData {
     pub password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Validate)]
+#[derive(Serialize, Deserialize, Debug, Default, Validate)]
+#[validate(context = EventChoices)]
 pub struct NewEventData {
     #[validate(length(
         min=4,
@@ -38,65 +40,312 @@ pub struct NewEventData {
     pub title: String,
     #[validate(url(message = "URL not valid."))]
     pub url: String,
-    #[validate(custom(function = "validate_event_location"))]
+    #[validate(custom(function = "validate_event_location", use_context))]
     pub location: String,
     #[validate(custom(function = "validate_event_date"))]
     pub date: String,
-    #[validate(custom(function = "validate_event_category"))]
+    #[validate(custom(function = "validate_event_category", use_context))]
+    pub category: String,
+    #[serde(default)]
+    #[validate(length(
+        max=2000,
+        message="event description should be at most 2000 characters."
+    ))]
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Validate)]
+#[validate(context = EventChoices)]
+pub struct EventFilter {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    #[validate(custom(function = "validate_event_location", use_context))]
+    pub location: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    #[validate(custom(function = "validate_event_category", use_context))]
+    pub category: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    #[validate(custom(function = "validate_event_date"))]
+    pub from: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    #[validate(custom(function = "validate_event_date"))]
+    pub to: Option<String>,
+}
+
+/// One row of an event listing, with the organizer's username and the
+/// number of attendees already joined and counted by the database.
+#[derive(Serialize, Debug, FromQueryResult)]
+pub struct EventListItem {
+    pub id: i32,
+    pub title: String,
+    pub url: String,
+    pub location: String,
+    pub date: String,
+    pub category: String,
+    pub username: String,
+    pub attendee_count: i64,
+    /// Whether the logged in user has marked themselves as going
+    pub is_going: bool,
+}
+
+/// An event listing row from `/search`, with the matched words of each
+/// searched field wrapped in `<mark>`. The `_html` fields are already escaped.
+#[derive(Serialize, Debug)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub event: EventListItem,
+    pub title_html: String,
+    pub category_html: String,
+    pub location_html: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+}
+
+/// One event as the JSON API returns it. Timestamps are RFC 3339 in UTC.
+#[derive(Serialize, Debug)]
+pub struct ApiEvent {
+    pub id: i32,
+    pub title: String,
+    pub url: String,
+    pub location: String,
+    pub date: String,
     pub category: String,
+    pub description: String,
+    pub username: String,
+    pub attendee_count: u64,
+    pub is_going: bool,
+    pub created_at: String,
+    pub updated_at: Option<String>,
+}
+
+/// A page of the JSON event listing.
+#[derive(Serialize, Debug)]
+pub struct ApiEventPage {
+    pub events: Vec<EventListItem>,
+    pub page: u64,
+    pub num_pages: u64,
+}
+
+/// Whether the current user is going after an RSVP toggle.
+#[derive(Serialize, Debug)]
+pub struct ApiRsvp {
+    pub event_id: i32,
+    pub is_going: bool,
+    pub attendee_count: u64,
+}
+
+/// The logged in user. With a session, `csrf_token` goes in the
+/// `X-CSRF-Token` header of requests that change something.
+#[derive(Serialize, Debug)]
+pub struct ApiUser {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csrf_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Validate)]
+pub struct NewApiTokenData {
+    #[validate(length(
+        min=1,
+        max=50,
+        message="token name should be between 1 to 50 characters."
+    ))]
+    pub name: String,
+}
+
+/// The events a user is going to during one calendar week (Monday to Sunday).
+#[derive(Serialize, Debug)]
+pub struct AgendaWeek {
+    pub label: String,
+    pub events: Vec<EventListItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Pagination {
+    #[serde(default = "first_page")]
+    pub page: u64,
+}
+
+fn first_page() -> u64 {
+    1
+}
+
+pub const EVENTS_PER_PAGE: u64 = 30;
+
+pub const FLAG_REASONS: [&str; 5] = [
+    "Spam",
+    "Fake or broken link",
+    "Joke, not a real event",
+    "Offensive",
+    "Other",
+];
+
+/// Treats `?location=` the same as a missing parameter, which is what
+/// an untouched `<select>` or date input in the filter form submits.
+fn empty_string_as_none<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.filter(|v| !v.trim().is_empty()))
 }
 
-fn validate_event_location(location: &str) -> Result<(), ValidationError> {
-    if EVENT_LOCATIONS.contains(&location) {
+fn validate_event_location(location: &str, choices: &EventChoices) -> Result<(), ValidationError> {
+    if choices.locations.iter().any(|name| name == location) {
         Ok(())
     } else {
-        Err(ValidationError::new("invalid_location"))
+        Err(ValidationError::new("invalid_location").with_message("Pick a location from the list.".into()))
     }
 }
 
 fn validate_event_date(date: &str) -> Result<(), ValidationError> {
     match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
         Ok(_) => Ok(()),
-        Err(_) => Err(ValidationError::new("invalid_date")),
+        Err(_) => Err(ValidationError::new("invalid_date").with_message("Date should look like YYYY-MM-DD.".into())),
     }
 }
 
-fn validate_event_category(category: &str) -> Result<(), ValidationError> {
-    if EVENT_CATEGORIES.contains(&category) {
+fn validate_event_category(category: &str, choices: &EventChoices) -> Result<(), ValidationError> {
+    if choices.categories.iter().any(|name| name == category) {
         Ok(())
     } else {
-        Err(ValidationError::new("invalid_category"))
+        Err(ValidationError::new("invalid_category").with_message("Pick a category from the list.".into()))
     }
 }
 
-pub const EVENT_LOCATIONS: [&str; 20] = [
-        "Stockholm",
-        "Göteborg",
-        "Malmö",
-        "Uppsala",
-        "Västerås",
-        "Örebro",
-        "Linköping",
-        "Helsingborg",
-        "Jönköping",
-        "Norrköping",
-        "Lund",
-        "Umeå",
-        "Gävle",
-        "Borås",
-        "Eskilstuna",
-        "Södertälje",
-        "Karlstad",
-        "Täby",
-        "Växjö",
-        "Halmstad",
-    ];
-
-pub const EVENT_CATEGORIES: [&str; 6] = [
-        "Languages",
-        "Sports",
-        "Social",
-        "Arts and theatre",
-        "Xmas",
-        "Other",
-    ];
\ No newline at end of file
+fn validate_flag_reason(reason: &str) -> Result<(), ValidationError> {
+    if FLAG_REASONS.contains(&reason) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("invalid_reason").with_message("Pick a reason from the list.".into()))
+    }
+}
+
+/// The location and category names an event may use, loaded from the
+/// `locations` and `categories` tables. Validation context for
+/// `NewEventData` and `EventFilter`, and the options of their `<select>`s.
+#[derive(Serialize, Debug, Default)]
+pub struct EventChoices {
+    pub locations: Vec<String>,
+    pub categories: Vec<String>,
+}
+
+impl EventChoices {
+    /// Keeps an event's current location and category valid while it is
+    /// edited, even if an admin has retired them since.
+    pub fn allow(mut self, location: &str, category: &str) -> Self {
+        if !self.locations.iter().any(|name| name == location) {
+            self.locations.push(location.to_string());
+        }
+        if !self.categories.iter().any(|name| name == category) {
+            self.categories.push(category.to_string());
+        }
+        self
+    }
+}
+
+/// An entry on the admin pages for locations and categories.
+#[derive(Serialize, Debug)]
+pub struct ChoiceRow {
+    pub id: i32,
+    pub name: String,
+    pub retired: bool,
+    pub event_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Validate)]
+pub struct ChoiceNameData {
+    #[validate(length(
+        min=1,
+        max=40,
+        message="name should be between 1 to 40 characters."
+    ))]
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Validate)]
+pub struct NewFlagData {
+    #[validate(custom(function = "validate_flag_reason"))]
+    pub reason: String,
+    #[serde(default)]
+    #[validate(length(
+        max=500,
+        message="comment should be at most 500 characters."
+    ))]
+    pub comment: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserRoleData {
+    pub role: String,
+}
+
+/// The moderation buttons post this. `back` is the admin page to return to,
+/// left out by the buttons on an event's own page.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ModerationData {
+    #[serde(default)]
+    pub back: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ForgotPasswordData {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Validate)]
+pub struct ResetPasswordData {
+    #[validate(length(
+        min=8,
+        max=15,
+        message="password should be between 8 to 15 characters."
+    ))]
+    pub password: String,
+    #[validate(must_match(other=password, message="Passwords not identical."))]
+    pub confirm_password: String,
+}
+
+/// A code from an authenticator app, or a recovery code at login.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TotpCodeData {
+    pub code: String,
+}
+
+/// The current password, asked again before two-factor settings change.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PasswordCheckData {
+    pub password: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::Validate;
+
+    use super::{ResetPasswordData, SignupData};
+
+    fn signup(password: &str) -> SignupData {
+        SignupData {
+            email: String::from("alice@example.com"),
+            username: String::from("alice"),
+            password: password.to_string(),
+            confirm_password: password.to_string(),
+        }
+    }
+
+    fn reset(password: &str) -> ResetPasswordData {
+        ResetPasswordData { password: password.to_string(), confirm_password: password.to_string() }
+    }
+
+    #[test]
+    fn signup_and_reset_take_the_same_passwords() {
+        for (password, valid) in [("1234", false), ("1234567", false), ("12345678", true), ("123456789012345", true), ("1234567890123456", false)] {
+            assert_eq!(signup(password).validate().is_ok(), valid, "signup with {:?}", password);
+            assert_eq!(reset(password).validate().is_ok(), valid, "reset with {:?}", password);
+        }
+    }
+}
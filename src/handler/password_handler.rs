@@ -0,0 +1,222 @@
+// External crates
+use axum::{
+    routing::get,
+    extract::{State, Path},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Router,
+    Form,
+};
+use axum_messages::Messages;
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use sea_orm::{
+    sea_query::Expr,
+    ActiveModelTrait,
+    ColumnTrait,
+    DatabaseConnection,
+    DbErr,
+    EntityTrait,
+    QueryFilter,
+    Set,
+    TransactionTrait,
+};
+use tera::Context;
+use tower_sessions::Session;
+use validator::Validate;
+
+// Internal modules
+use crate::AppState;
+use crate::csrf::csrf_token;
+use crate::error::AppError;
+use crate::handler::models::{ ForgotPasswordData, ResetPasswordData };
+use crate::entities::{ password_resets, users };
+use crate::entities::password_resets::Entity as PasswordReset;
+use crate::entities::users::Entity as User;
+use crate::helper::{ field_errors, hash_password, hash_token, random_token };
+use crate::throttle::{ account_key, lift_lockout };
+
+/// How long an emailed reset link works.
+const RESET_LINK_HOURS: i64 = 1;
+
+pub fn password_router() -> Router<AppState> {
+    Router::new()
+    .route("/forgot", get(forgot).post(send_reset_link))
+    .route("/reset/{token}", get(reset_form).post(reset_password))
+}
+
+pub async fn forgot(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let context = form_context(&session, messages, "Forgot password").await?;
+    Ok(Html(app_state.tera.render("partials/forgot.html", &context)?).into_response())
+}
+
+/// Mails a reset link if the address belongs to an account. The answer is
+/// the same either way, so the form can't be used to find out who has one.
+/// The link is stored and mailed in the background, so a known address
+/// doesn't take longer to answer than an unknown one.
+pub async fn send_reset_link(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    Form(data): Form<ForgotPasswordData>,
+) -> Result<Response, AppError> {
+    let user = User::find()
+        .filter(users::Column::Email.eq(data.email.trim()))
+        .one(&app_state.db_connection)
+        .await?;
+
+    if let Some(user) = user {
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            // Failing here would tell the visitor the account exists, so only log it
+            if let Err(err) = mail_reset_link(&app_state, &user).await {
+                eprintln!("{}", AppError::from(err));
+            }
+        });
+    }
+
+    messages.info(format!(
+        "If an account uses that email, a reset link is on its way. It works for {} hour.",
+        RESET_LINK_HOURS,
+    ));
+    Ok(Redirect::to("/login").into_response())
+}
+
+/// Stores a fresh reset link for `user` and mails it. A mail that can't be
+/// sent is logged; the visitor can ask for another link.
+async fn mail_reset_link(app_state: &AppState, user: &users::Model) -> Result<(), DbErr> {
+    let token = random_token();
+    let reset = password_resets::ActiveModel {
+        user_id: Set(user.id),
+        token_hash: Set(hash_token(&token)),
+        expires_at: Set(Utc::now().naive_utc() + Duration::hours(RESET_LINK_HOURS)),
+        ..Default::default()
+    };
+    let _ = reset.insert(&app_state.db_connection).await?;
+
+    let body = format!(
+        "Hi {},\n\nSomeone asked to reset the password of your Happening nu account. \
+        If it was you, choose a new one here:\n\n{}/reset/{}\n\n\
+        The link works once, for {} hour. If it wasn't you, ignore this mail and nothing changes.\n",
+        user.username, app_state.base_url, token, RESET_LINK_HOURS,
+    );
+    if let Err(err) = app_state.mailer.send(&user.email, "Reset your Happening nu password", body).await {
+        eprintln!("{}", err);
+    }
+    Ok(())
+}
+
+pub async fn reset_form(
+    State(app_state): State<AppState>,
+    Path(token): Path<String>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let reset = valid_reset(&app_state.db_connection, &token).await?;
+    render_reset_form(&app_state, &session, messages, &token, reset.is_some(), &HashMap::new()).await
+}
+
+/// Sets the new password, uses up the link, lifts any login lockout and
+/// ends every session the account has, this one included.
+pub async fn reset_password(
+    State(app_state): State<AppState>,
+    Path(token): Path<String>,
+    messages: Messages,
+    session: Session,
+    Form(data): Form<ResetPasswordData>,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let Some(reset) = valid_reset(db, &token).await? else {
+        return render_reset_form(&app_state, &session, messages, &token, false, &HashMap::new()).await;
+    };
+    if let Err(errors) = data.validate() {
+        return render_reset_form(&app_state, &session, messages, &token, true, &field_errors(&errors)).await;
+    }
+    let Some(user) = User::find_by_id(reset.user_id).one(db).await? else {
+        return Err(AppError::NotFound);
+    };
+    let account = account_key(&user.email);
+
+    let now = Utc::now().naive_utc();
+    let txn = db.begin().await?;
+    // Claiming the link first makes it single use even when the form is
+    // sent twice at once; the request that loses the race changes nothing
+    let claimed = PasswordReset::update_many()
+        .col_expr(password_resets::Column::UsedAt, Expr::value(now))
+        .filter(password_resets::Column::Id.eq(reset.id))
+        .filter(password_resets::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await?;
+    if claimed.rows_affected != 1 {
+        txn.rollback().await?;
+        return render_reset_form(&app_state, &session, messages, &token, false, &HashMap::new()).await;
+    }
+    let session_version = user.session_version + 1;
+    let mut active_model: users::ActiveModel = user.into();
+    active_model.password = Set(hash_password(&data.password)?);
+    active_model.legacy_salt = Set(false);
+    active_model.session_version = Set(session_version);
+    let _ = active_model.update(&txn).await?;
+    // Any other link still in someone's inbox stops working too
+    let _ = PasswordReset::update_many()
+        .col_expr(password_resets::Column::UsedAt, Expr::value(now))
+        .filter(password_resets::Column::UserId.eq(reset.user_id))
+        .filter(password_resets::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+    lift_lockout(db, &account).await?;
+
+    session.remove::<i32>("user_id").await?;
+    messages.info("Your password has been changed. Log in with the new one.");
+    Ok(Redirect::to("/login").into_response())
+}
+
+/// The reset behind `token` if it exists, is unused and hasn't expired.
+async fn valid_reset(db: &DatabaseConnection, token: &str) -> Result<Option<password_resets::Model>, DbErr> {
+    PasswordReset::find()
+        .filter(password_resets::Column::TokenHash.eq(hash_token(token)))
+        .filter(password_resets::Column::UsedAt.is_null())
+        .filter(password_resets::Column::ExpiresAt.gt(Utc::now().naive_utc()))
+        .one(db)
+        .await
+}
+
+/// Renders the new password form, or an explanation when the link can't be
+/// used (404). Comes back as 422 when there are field errors to show.
+async fn render_reset_form(
+    app_state: &AppState,
+    session: &Session,
+    messages: Messages,
+    token: &str,
+    link_valid: bool,
+    errors: &HashMap<String, Vec<String>>,
+) -> Result<Response, AppError> {
+    let mut context = form_context(session, messages, "Choose a new password").await?;
+    context.insert("token", token);
+    context.insert("link_valid", &link_valid);
+    context.insert("errors", errors);
+    let html = Html(app_state.tera.render("partials/reset_password.html", &context)?);
+    if !link_valid {
+        Ok((StatusCode::NOT_FOUND, html).into_response())
+    } else if !errors.is_empty() {
+        Ok((StatusCode::UNPROCESSABLE_ENTITY, html).into_response())
+    } else {
+        Ok(html.into_response())
+    }
+}
+
+async fn form_context(session: &Session, messages: Messages, title: &str) -> Result<Context, AppError> {
+    let mut context = Context::new();
+    context.insert("title", title);
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(session).await?);
+    Ok(context)
+}
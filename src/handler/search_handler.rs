@@ -0,0 +1,173 @@
+// External crates
+use axum::{
+    routing::get,
+    extract::{State, Query},
+    http::HeaderMap,
+    response::Html,
+    Router,
+};
+use axum_messages::Messages;
+use sea_orm::{
+    ConnectionTrait,
+    DatabaseConnection,
+    DbBackend,
+    DbErr,
+    FromQueryResult,
+    Statement,
+};
+use tera::Context;
+use tower_sessions::Session;
+
+// Internal modules
+use crate::AppState;
+use crate::csrf::csrf_token;
+use crate::error::AppError;
+use crate::handler::models::{ EventListItem, SearchQuery, SearchResult };
+use crate::helper::get_username_from_session;
+
+/// Most results a search shows, best matches first.
+const SEARCH_LIMIT: u64 = 50;
+// `highlight()` wraps matches in these, they become <mark> once the rest is escaped
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+pub fn search_router() -> Router<AppState> {
+    Router::new()
+    .route("/search", get(search))
+}
+
+pub async fn search(
+    State(app_state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+    headers: HeaderMap,
+    messages: Messages,
+    session: Session,
+) -> Result<Html<String>, AppError> {
+    let tera = &app_state.tera;
+    let mut context = Context::new();
+
+    let user_id = session.get::<i32>("user_id").await.unwrap_or(None);
+    let q = query.q.trim();
+    let results = search_events(&app_state.db_connection, q, user_id).await?;
+    context.insert("is_logged_in", &user_id.is_some());
+    context.insert("all_events", &results);
+    context.insert("q", q);
+
+    // The navbar's live search only needs the rows
+    if headers.contains_key("HX-Request") {
+        return Ok(Html(tera.render("partials/search_rows.html", &context)?));
+    }
+
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+
+    let logged_in_username = get_username_from_session(&session, &app_state.db_connection).await?;
+    context.insert("logged_in_username", &logged_in_username);
+    context.insert("not_home", &true);
+    context.insert("search_target", "#search_results");
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(&session).await?);
+    context.insert("title", "Search");
+    Ok(Html(tera.render("partials/search.html", &context)?))
+}
+
+/// Events matching every word of `q` in their title, category or location,
+/// ranked by `bm25()` with title matches counting the most.
+pub async fn search_events(
+    db: &DatabaseConnection,
+    q: &str,
+    viewer: Option<i32>,
+) -> Result<Vec<SearchResult>, DbErr> {
+    let Some(fts_query) = fts_query(q) else {
+        return Ok(Vec::new());
+    };
+
+    let statement = Statement::from_sql_and_values(
+        DbBackend::Sqlite,
+        format!(
+            r#"SELECT "events"."id", "events"."title", "events"."url", "events"."location",
+                "events"."date", "events"."category",
+                COALESCE("users"."username", 'unknown') AS "username",
+                (SELECT COUNT(*) FROM "user_events" WHERE "user_events"."event_id" = "events"."id") AS "attendee_count",
+                EXISTS(SELECT 1 FROM "user_events" WHERE "user_events"."event_id" = "events"."id"
+                    AND "user_events"."user_id" = ?) AS "is_going",
+                "matches"."title_match", "matches"."category_match", "matches"."location_match"
+            FROM (
+                SELECT events_fts.rowid,
+                    highlight(events_fts, 0, ?, ?) AS "title_match",
+                    highlight(events_fts, 1, ?, ?) AS "category_match",
+                    highlight(events_fts, 2, ?, ?) AS "location_match",
+                    bm25(events_fts, 10.0, 2.0, 2.0) AS "rank"
+                FROM events_fts
+                JOIN "events" AS "visible" ON "visible"."id" = events_fts.rowid
+                WHERE events_fts MATCH ?
+                    -- Hidden events are dropped before the limit, not after it
+                    AND "visible"."hidden_at" IS NULL
+                ORDER BY "rank"
+                LIMIT {}
+            ) AS "matches"
+            JOIN "events" ON "events"."id" = "matches"."rowid"
+            LEFT JOIN "users" ON "users"."id" = "events"."user_id"
+            ORDER BY "matches"."rank""#,
+            SEARCH_LIMIT,
+        ),
+        [
+            viewer.into(),
+            MATCH_START.to_string().into(),
+            MATCH_END.to_string().into(),
+            MATCH_START.to_string().into(),
+            MATCH_END.to_string().into(),
+            MATCH_START.to_string().into(),
+            MATCH_END.to_string().into(),
+            fts_query.into(),
+        ],
+    );
+
+    let rows = db.query_all(statement).await?;
+    rows.iter()
+        .map(|row| {
+            Ok(SearchResult {
+                event: EventListItem::from_query_result(row, "")?,
+                title_html: highlighted_html(&row.try_get::<String>("", "title_match")?),
+                category_html: highlighted_html(&row.try_get::<String>("", "category_match")?),
+                location_html: highlighted_html(&row.try_get::<String>("", "location_match")?),
+            })
+        })
+        .collect()
+}
+
+/// Turns what was typed into an FTS5 query: each word becomes a quoted
+/// prefix match, so `fik mal` finds "Fika meetup" in Malmö and nothing the
+/// user types is read as FTS5 syntax. `None` when there are no words.
+fn fts_query(q: &str) -> Option<String> {
+    let terms: Vec<String> = q
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"*", word))
+        .collect();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Escapes the text for HTML, then turns the highlight markers into `<mark>`.
+fn highlighted_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#x27;"),
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            c => html.push(c),
+        }
+    }
+    html
+}
@@ -0,0 +1,166 @@
+// External crates
+use axum::{
+    routing::{get, post},
+    extract::{State, Path},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Router,
+    Form,
+};
+use axum_messages::Messages;
+use std::collections::HashMap;
+use sea_orm::{
+    ActiveModelTrait,
+    ColumnTrait,
+    EntityTrait,
+    QueryFilter,
+    QueryOrder,
+    Set,
+};
+use tera::Context;
+use tower_sessions::Session;
+use validator::Validate;
+
+// Internal modules
+use crate::AppState;
+use crate::auth::Role;
+use crate::csrf::csrf_token;
+use crate::error::AppError;
+use crate::handler::models::NewApiTokenData;
+use crate::entities::api_tokens;
+use crate::entities::api_tokens::Entity as ApiToken;
+use crate::entities::users::Entity as User;
+use crate::helper::{ field_errors, get_username_from_session, get_role_from_session, hash_token, random_token };
+
+pub fn settings_router() -> Router<AppState> {
+    Router::new()
+    .route("/settings", get(settings))
+    .route("/settings/tokens", post(create_api_token))
+    .route("/settings/tokens/{id}/delete", post(revoke_api_token))
+}
+
+pub async fn settings(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        // Not logged in, redirect to login page
+        return Ok(Redirect::to("/login").into_response());
+    };
+    render_settings(&app_state, &session, messages, uid, &NewApiTokenData::default(), &HashMap::new(), None).await
+}
+
+pub async fn create_api_token(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+    Form(data): Form<NewApiTokenData>,
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let data = NewApiTokenData { name: data.name.trim().to_string() };
+    if let Err(errors) = data.validate() {
+        return render_settings(&app_state, &session, messages, uid, &data, &field_errors(&errors), None).await;
+    }
+
+    let token = format!("hn_{}", random_token());
+    let new_token = api_tokens::ActiveModel {
+        user_id: Set(uid),
+        name: Set(data.name.clone()),
+        token_hash: Set(hash_token(&token)),
+        ..Default::default()
+    };
+    let _ = new_token.insert(&app_state.db_connection).await?;
+
+    // The plain token is only ever shown in this response, never stored
+    render_settings(&app_state, &session, messages, uid, &NewApiTokenData::default(), &HashMap::new(), Some(&token)).await
+}
+
+pub async fn revoke_api_token(
+    State(app_state): State<AppState>,
+    Path(token_id): Path<i32>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    // Someone else's token looks the same as a missing one
+    let Some(api_token) = ApiToken::find_by_id(token_id)
+        .filter(api_tokens::Column::UserId.eq(uid))
+        .one(&app_state.db_connection)
+        .await?
+    else {
+        return Err(AppError::NotFound);
+    };
+
+    let name = api_token.name.clone();
+    let active_model: api_tokens::ActiveModel = api_token.into();
+    let _ = active_model.delete(&app_state.db_connection).await?;
+    messages.info(format!("Token \"{}\" revoked.", name));
+    Ok(Redirect::to("/settings").into_response())
+}
+
+/// Renders the settings page with the user's API tokens. `new_token` is the
+/// plain text of a token that was just created, shown once.
+async fn render_settings(
+    app_state: &AppState,
+    session: &Session,
+    messages: Messages,
+    user_id: i32,
+    form: &NewApiTokenData,
+    errors: &HashMap<String, Vec<String>>,
+    new_token: Option<&str>,
+) -> Result<Response, AppError> {
+    let tera = &app_state.tera;
+    let mut context = Context::new();
+
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+
+    context.insert("is_logged_in", &true);
+    let logged_in_username = get_username_from_session(session, &app_state.db_connection).await?;
+    context.insert("logged_in_username", &logged_in_username);
+    context.insert("not_home", &true);
+
+    let tokens: Vec<serde_json::Value> = ApiToken::find()
+        .filter(api_tokens::Column::UserId.eq(user_id))
+        .order_by_desc(api_tokens::Column::CreatedAt)
+        .all(&app_state.db_connection)
+        .await?
+        .into_iter()
+        .map(|token| serde_json::json!({
+            "id": token.id,
+            "name": token.name,
+            "created_at": token.created_at.format("%Y-%m-%d %H:%M").to_string(),
+            "last_used_at": token.last_used_at.map(|t| t.format("%Y-%m-%d %H:%M").to_string()),
+        }))
+        .collect();
+
+    context.insert("api_tokens", &tokens);
+    if let Some(user) = User::find_by_id(user_id).one(&app_state.db_connection).await? {
+        context.insert("email", &user.email);
+        context.insert("email_verified", &user.email_verified_at.is_some());
+        context.insert("two_factor_enabled", &user.totp_secret.is_some());
+    }
+    let role = get_role_from_session(session, &app_state.db_connection).await?;
+    context.insert("is_staff", &(role >= Role::Moderator));
+    context.insert("new_token", &new_token);
+    context.insert("form", form);
+    context.insert("errors", errors);
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(session).await?);
+    context.insert("title", "Settings");
+    let html = Html(tera.render("partials/settings.html", &context)?);
+    if errors.is_empty() {
+        Ok(html.into_response())
+    } else {
+        Ok((StatusCode::UNPROCESSABLE_ENTITY, html).into_response())
+    }
+}
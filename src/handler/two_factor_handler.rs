@@ -0,0 +1,377 @@
+// External crates
+use axum::{
+    routing::{get, post},
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Router,
+    Form,
+};
+use axum_messages::Messages;
+use chrono::Utc;
+use std::collections::HashMap;
+use sea_orm::{
+    sea_query::Expr,
+    ActiveModelTrait,
+    ColumnTrait,
+    Condition,
+    DatabaseConnection,
+    EntityTrait,
+    PaginatorTrait,
+    QueryFilter,
+    Set,
+    TransactionTrait,
+};
+use tera::Context;
+use tower_sessions::Session;
+
+// Internal modules
+use crate::AppState;
+use crate::auth::{ start_session, ClientIp, PendingLogin, PENDING_LOGIN };
+use crate::csrf::csrf_token;
+use crate::error::AppError;
+use crate::handler::models::{ PasswordCheckData, TotpCodeData };
+use crate::entities::{ recovery_codes, users };
+use crate::entities::recovery_codes::Entity as RecoveryCode;
+use crate::entities::users::Entity as User;
+use crate::helper::{ get_username_from_session, hash_token, password_matches };
+use crate::throttle::{ account_key, begin_attempt, client_key, login_succeeded, ACCOUNT, CLIENT };
+use crate::totp::{ accepted_step, new_recovery_codes, new_secret, normalize_recovery_code, qr_svg, setup_uri };
+
+/// Session key for the secret shown during setup, until a code confirms it.
+const SETUP_SECRET: &str = "totp_setup_secret";
+/// How long after the password check the code is still accepted.
+const SECOND_STEP_SECONDS: i64 = 300;
+/// Wrong codes allowed before the password has to be entered again.
+const SECOND_STEP_ATTEMPTS: u32 = 5;
+
+pub fn two_factor_router() -> Router<AppState> {
+    Router::new()
+    .route("/login/2fa", get(second_step_form).post(process_second_step))
+    .route("/settings/2fa", get(two_factor_settings))
+    .route("/settings/2fa/enable", post(enable_two_factor))
+    .route("/settings/2fa/recovery", post(regenerate_recovery_codes))
+    .route("/settings/2fa/disable", post(disable_two_factor))
+}
+
+pub async fn second_step_form(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    if session.get::<PendingLogin>(PENDING_LOGIN).await?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+    render_second_step(&app_state, &session, messages, None).await
+}
+
+/// Accepts a code from the authenticator or an unused recovery code, then
+/// finishes the login the password check started. Codes are throttled like
+/// passwords, against the same account.
+pub async fn process_second_step(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+    ClientIp(ip): ClientIp,
+    Form(data): Form<TotpCodeData>,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let Some(mut pending) = session.get::<PendingLogin>(PENDING_LOGIN).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    if Utc::now().timestamp() - pending.started_at > SECOND_STEP_SECONDS {
+        session.remove::<PendingLogin>(PENDING_LOGIN).await?;
+        messages.error("That took too long. Log in again.");
+        return Ok(Redirect::to("/login").into_response());
+    }
+    // The account may have been banned, reset or lost its second factor meanwhile
+    let user = User::find_by_id(pending.user_id).one(db).await?.filter(|user| {
+        user.banned_at.is_none() && user.session_version == pending.session_version && user.totp_secret.is_some()
+    });
+    let Some(user) = user else {
+        session.remove::<PendingLogin>(PENDING_LOGIN).await?;
+        messages.error("Log in again.");
+        return Ok(Redirect::to("/login").into_response());
+    };
+    let now = Utc::now().naive_utc();
+    let account = account_key(&user.email);
+    let client = client_key(&ip);
+    if let Some(blocked) = begin_attempt(db, &[(&account, &ACCOUNT), (&client, &CLIENT)], now).await? {
+        return render_second_step(&app_state, &session, messages, Some(&blocked.message())).await;
+    }
+
+    let mut recovery_notice = None;
+    if !use_totp_code(db, &user, &data.code).await? {
+        if let Some(left) = use_recovery_code(db, user.id, &data.code).await? {
+            recovery_notice = Some(format!("Recovery code used, {} left. Make new ones in your settings if you're running low.", left));
+        } else {
+            pending.failures += 1;
+            if pending.failures >= SECOND_STEP_ATTEMPTS {
+                session.remove::<PendingLogin>(PENDING_LOGIN).await?;
+                messages.error("Too many wrong codes. Log in again.");
+                return Ok(Redirect::to("/login").into_response());
+            }
+            session.insert(PENDING_LOGIN, &pending).await?;
+            return render_second_step(&app_state, &session, messages, Some("That code didn't work.")).await;
+        }
+    }
+
+    session.remove::<PendingLogin>(PENDING_LOGIN).await?;
+    start_session(&session, &user).await?;
+    login_succeeded(db, &account, &client, now).await?;
+    let messages = messages.info("Login successful!");
+    if let Some(notice) = recovery_notice {
+        messages.info(notice);
+    }
+    Ok(Redirect::to("/").into_response())
+}
+
+/// Whether `code` is a current authenticator code for `user` that hasn't
+/// been used yet. Accepting it moves `totp_last_step` forward, and only one
+/// of two requests racing with the same code gets to do that.
+async fn use_totp_code(db: &DatabaseConnection, user: &users::Model, code: &str) -> Result<bool, AppError> {
+    let Some(secret) = &user.totp_secret else {
+        return Ok(false);
+    };
+    let Some(step) = accepted_step(secret, &user.email, code, user.totp_last_step)? else {
+        return Ok(false);
+    };
+    let result = User::update_many()
+        .col_expr(users::Column::TotpLastStep, Expr::value(step))
+        .filter(users::Column::Id.eq(user.id))
+        .filter(
+            Condition::any()
+                .add(users::Column::TotpLastStep.is_null())
+                .add(users::Column::TotpLastStep.lt(step)),
+        )
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected == 1)
+}
+
+/// Uses up a recovery code, returning how many the user has left, or `None`
+/// when `code` isn't one of their unused ones.
+async fn use_recovery_code(db: &DatabaseConnection, user_id: i32, code: &str) -> Result<Option<u64>, AppError> {
+    let result = RecoveryCode::update_many()
+        .col_expr(recovery_codes::Column::UsedAt, Expr::value(Utc::now().naive_utc()))
+        .filter(recovery_codes::Column::UserId.eq(user_id))
+        .filter(recovery_codes::Column::CodeHash.eq(hash_token(&normalize_recovery_code(code))))
+        .filter(recovery_codes::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+    if result.rows_affected == 0 {
+        return Ok(None);
+    }
+    Ok(Some(unused_recovery_codes(db, user_id).await?))
+}
+
+async fn unused_recovery_codes(db: &DatabaseConnection, user_id: i32) -> Result<u64, AppError> {
+    Ok(RecoveryCode::find()
+        .filter(recovery_codes::Column::UserId.eq(user_id))
+        .filter(recovery_codes::Column::UsedAt.is_null())
+        .count(db)
+        .await?)
+}
+
+/// Replaces all of the user's recovery codes with new ones and returns them
+/// in plain text, which is the only time they exist outside the user's hands.
+async fn replace_recovery_codes<C: sea_orm::ConnectionTrait>(db: &C, user_id: i32) -> Result<Vec<String>, AppError> {
+    let _ = RecoveryCode::delete_many()
+        .filter(recovery_codes::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+    let codes = new_recovery_codes();
+    let rows = codes.iter().map(|code| recovery_codes::ActiveModel {
+        user_id: Set(user_id),
+        code_hash: Set(hash_token(&normalize_recovery_code(code))),
+        ..Default::default()
+    });
+    let _ = RecoveryCode::insert_many(rows).exec(db).await?;
+    Ok(codes)
+}
+
+pub async fn two_factor_settings(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let Some(user) = session_user(&app_state, &session).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    render_two_factor(&app_state, &session, messages, &user, None, &HashMap::new()).await
+}
+
+/// Turns two-factor on once a code shows the authenticator has the secret
+/// from the setup page, and hands out the first recovery codes.
+pub async fn enable_two_factor(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+    Form(data): Form<TotpCodeData>,
+) -> Result<Response, AppError> {
+    let Some(user) = session_user(&app_state, &session).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    if user.totp_secret.is_some() {
+        return Ok(Redirect::to("/settings/2fa").into_response());
+    }
+    let Some(secret) = session.get::<String>(SETUP_SECRET).await? else {
+        return Ok(Redirect::to("/settings/2fa").into_response());
+    };
+    let Some(step) = accepted_step(&secret, &user.email, &data.code, None)? else {
+        let errors = HashMap::from([
+            (String::from("code"), vec![String::from("That code didn't work. Check the time on your phone and try the next one.")]),
+        ]);
+        return render_two_factor(&app_state, &session, messages, &user, None, &errors).await;
+    };
+
+    let txn = app_state.db_connection.begin().await?;
+    let user_id = user.id;
+    let mut active_model: users::ActiveModel = user.into();
+    active_model.totp_secret = Set(Some(secret));
+    active_model.totp_last_step = Set(Some(step));
+    let user = active_model.update(&txn).await?;
+    let codes = replace_recovery_codes(&txn, user_id).await?;
+    txn.commit().await?;
+    session.remove::<String>(SETUP_SECRET).await?;
+    render_two_factor(&app_state, &session, messages, &user, Some(&codes), &HashMap::new()).await
+}
+
+pub async fn regenerate_recovery_codes(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+    Form(data): Form<PasswordCheckData>,
+) -> Result<Response, AppError> {
+    let Some(user) = session_user(&app_state, &session).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    if user.totp_secret.is_none() {
+        return Ok(Redirect::to("/settings/2fa").into_response());
+    }
+    if !password_matches(&data.password, &user.password)? {
+        return render_two_factor(&app_state, &session, messages, &user, None, &wrong_password()).await;
+    }
+
+    let txn = app_state.db_connection.begin().await?;
+    let codes = replace_recovery_codes(&txn, user.id).await?;
+    txn.commit().await?;
+    render_two_factor(&app_state, &session, messages, &user, Some(&codes), &HashMap::new()).await
+}
+
+pub async fn disable_two_factor(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+    Form(data): Form<PasswordCheckData>,
+) -> Result<Response, AppError> {
+    let Some(user) = session_user(&app_state, &session).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    if user.totp_secret.is_none() {
+        return Ok(Redirect::to("/settings/2fa").into_response());
+    }
+    if !password_matches(&data.password, &user.password)? {
+        return render_two_factor(&app_state, &session, messages, &user, None, &wrong_password()).await;
+    }
+
+    let txn = app_state.db_connection.begin().await?;
+    let user_id = user.id;
+    let mut active_model: users::ActiveModel = user.into();
+    active_model.totp_secret = Set(None);
+    active_model.totp_last_step = Set(None);
+    let _ = active_model.update(&txn).await?;
+    let _ = RecoveryCode::delete_many()
+        .filter(recovery_codes::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+    messages.info("Two-factor authentication is off.");
+    Ok(Redirect::to("/settings/2fa").into_response())
+}
+
+async fn session_user(app_state: &AppState, session: &Session) -> Result<Option<users::Model>, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        return Ok(None);
+    };
+    Ok(User::find_by_id(uid).one(&app_state.db_connection).await?)
+}
+
+fn wrong_password() -> HashMap<String, Vec<String>> {
+    HashMap::from([(String::from("password"), vec![String::from("Wrong password.")])])
+}
+
+async fn render_second_step(
+    app_state: &AppState,
+    session: &Session,
+    messages: Messages,
+    error: Option<&str>,
+) -> Result<Response, AppError> {
+    let mut context = Context::new();
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+    context.insert("title", "Two-factor authentication");
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(session).await?);
+    context.insert("error", &error);
+    let html = Html(app_state.tera.render("partials/login_2fa.html", &context)?);
+    if error.is_none() {
+        Ok(html.into_response())
+    } else {
+        Ok((StatusCode::UNPROCESSABLE_ENTITY, html).into_response())
+    }
+}
+
+/// Renders the two-factor settings: the setup QR code while it is off, the
+/// recovery code and disable forms while it is on. `new_codes` are shown
+/// once, right after they were made.
+async fn render_two_factor(
+    app_state: &AppState,
+    session: &Session,
+    messages: Messages,
+    user: &users::Model,
+    new_codes: Option<&[String]>,
+    errors: &HashMap<String, Vec<String>>,
+) -> Result<Response, AppError> {
+    let tera = &app_state.tera;
+    let mut context = Context::new();
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+    context.insert("is_logged_in", &true);
+    let logged_in_username = get_username_from_session(session, &app_state.db_connection).await?;
+    context.insert("logged_in_username", &logged_in_username);
+    context.insert("not_home", &true);
+
+    let enabled = user.totp_secret.is_some();
+    context.insert("enabled", &enabled);
+    if enabled {
+        context.insert("recovery_codes_left", &unused_recovery_codes(&app_state.db_connection, user.id).await?);
+    } else {
+        // Keep one secret per setup so reloading doesn't invalidate a scanned code
+        let secret = match session.get::<String>(SETUP_SECRET).await? {
+            Some(secret) => secret,
+            None => {
+                let secret = new_secret();
+                session.insert(SETUP_SECRET, &secret).await?;
+                secret
+            }
+        };
+        context.insert("qr_code", &qr_svg(&setup_uri(&secret, &user.email)?)?);
+        context.insert("secret", &secret);
+    }
+    context.insert("new_codes", &new_codes);
+    context.insert("errors", errors);
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(session).await?);
+    context.insert("title", "Two-factor authentication");
+    let html = Html(tera.render("partials/two_factor.html", &context)?);
+    if errors.is_empty() {
+        Ok(html.into_response())
+    } else {
+        Ok((StatusCode::UNPROCESSABLE_ENTITY, html).into_response())
+    }
+}
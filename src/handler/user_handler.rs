@@ -1,18 +1,15 @@
 // External crates
-use argon2::{
-    password_hash::{
-        PasswordHash, PasswordHasher, PasswordVerifier, SaltString
-    },
-    Argon2
-};
 use axum::{
     routing::{get, post},
     extract::State,
-    response::{Html, IntoResponse, Redirect},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
     Router,
     Form,
 };
 use axum_messages::Messages;
+use chrono::Utc;
+use std::collections::HashMap;
 use sea_orm::{
     ActiveModelTrait,
     EntityTrait,
@@ -26,9 +23,15 @@ use validator::Validate;
 
 // Internal modules
 use crate::AppState;
+use crate::auth::{ begin_second_step, start_session, ClientIp };
+use crate::csrf::csrf_token;
+use crate::error::AppError;
 use crate::handler::models::{ SignupData, LoginData };
+use crate::handler::verification_handler::send_verification_link;
 use crate::entities::users;
 use crate::entities::users::Entity as User;
+use crate::helper::{ field_errors, hash_password, password_matches, waste_password_check };
+use crate::throttle::{ account_key, begin_attempt, client_key, login_succeeded, ACCOUNT, CLIENT };
 
 pub fn user_router() -> Router<AppState> {
     Router::new()
@@ -43,11 +46,11 @@ pub async fn login(
     State(app_state): State<AppState>,
     messages: Messages,
     session: Session,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
     let tera = &app_state.tera;
     let mut context = Context::new();
     if session.get::<i32>("user_id").await.unwrap_or(None).is_some() {
-        return Redirect::to("/").into_response();
+        return Ok(Redirect::to("/").into_response());
     }
     let mut info_to_user: Vec<String> = vec![];
     for msg in messages.into_iter() {
@@ -55,117 +58,180 @@ pub async fn login(
     }
     context.insert("title", "Log in");
     context.insert("messages", &info_to_user);
-    Html(tera.render("partials/login.html", &context).unwrap()).into_response()
+    context.insert("csrf_token", &csrf_token(&session).await?);
+    Ok(Html(tera.render("partials/login.html", &context)?).into_response())
 }
 
 async fn process_login_form(
     State(app_state): State<AppState>,
     messages: Messages,
     session: Session,
+    ClientIp(ip): ClientIp,
     Form(data): Form<LoginData>,
-) -> Redirect {
+) -> Result<Redirect, AppError> {
     let mut context = Context::new();
+    let now = Utc::now().naive_utc();
+    let account = account_key(&data.email);
+    let client = client_key(&ip);
+    if let Some(blocked) = begin_attempt(&app_state.db_connection, &[(&account, &ACCOUNT), (&client, &CLIENT)], now).await? {
+        // Turned away before the slow password check
+        messages.error(blocked.message());
+        return Ok(Redirect::to("/login"));
+    }
+
     let user = User::find()
         .filter(users::Column::Email.eq(data.email.clone()))
         .one(&app_state.db_connection)
-        .await
-        .unwrap();
+        .await?;
 
-    if let Some(user) = user {
-        let parsed_hash = PasswordHash::new(&user.password).unwrap();
-        let password_bytes = data.password.as_bytes();
-        let is_valid = Argon2::default()
-            .verify_password(password_bytes, &parsed_hash)
-            .is_ok();
-
-        if is_valid {
-            session.insert("user_id", user.id).await.unwrap();
-            let is_logged_in = true;
-            context.insert("is_logged_in", &is_logged_in);
-            messages.info("Login successful!");
-            Redirect::to("/")
-        } else {
-            messages.error("Invalid password.");
-            Redirect::to("/login")
+    // An unknown email and a wrong password look and take the same
+    let is_valid = match &user {
+        Some(user) => password_matches(&data.password, &user.password)?,
+        None => {
+            waste_password_check(&data.password);
+            false
         }
-    } else {
-        messages.error("Not found.");
-        Redirect::to("/login")
+    };
+    let Some(user) = user.filter(|_| is_valid) else {
+        messages.error("Wrong email or password.");
+        return Ok(Redirect::to("/login"));
+    };
+
+    if user.banned_at.is_some() {
+        messages.error("This account has been banned.");
+        return Ok(Redirect::to("/login"));
+    }
+    if user.legacy_salt {
+        // Swap the shared salt for a per-user one while we have the plain password
+        let mut active_model: users::ActiveModel = user.clone().into();
+        active_model.password = Set(hash_password(&data.password)?);
+        active_model.legacy_salt = Set(false);
+        let _ = active_model.update(&app_state.db_connection).await?;
     }
+    if user.totp_secret.is_some() {
+        // The attempt stays counted until the code is right too
+        begin_second_step(&session, &user).await?;
+        return Ok(Redirect::to("/login/2fa"));
+    }
+    start_session(&session, &user).await?;
+    login_succeeded(&app_state.db_connection, &account, &client, now).await?;
+    let is_logged_in = true;
+    context.insert("is_logged_in", &is_logged_in);
+    messages.info("Login successful!");
+    Ok(Redirect::to("/"))
 }
 
 pub async fn logout(
     session: Session,
     messages: Messages,
-) -> Redirect {
-    session.remove::<i32>("user_id").await.unwrap();
+) -> Result<Redirect, AppError> {
+    session.remove::<i32>("user_id").await?;
     messages.info("You have logged out.");
-    Redirect::to("/")
+    Ok(Redirect::to("/"))
 }
 
 pub async fn signup(
     State(app_state): State<AppState>,
     messages: Messages,
     session: Session,
-) -> impl IntoResponse {
-    let tera = &app_state.tera;
-    let mut context = Context::new();
+) -> Result<Response, AppError> {
     if session.get::<i32>("user_id").await.unwrap_or(None).is_some() {
-        return Redirect::to("/").into_response();
-    }
-    context.insert("title", "Sign up");
-    let mut info_to_user: Vec<String> = vec![];
-    for msg in messages.into_iter() {
-        info_to_user.push(msg.message);
+        return Ok(Redirect::to("/").into_response());
     }
-    context.insert("messages", &info_to_user);
-    Html(tera.render("partials/signup.html", &context).unwrap()).into_response()
+    render_signup_form(&app_state, &session, messages, &SignupData::default(), &HashMap::new()).await
 }
 
+/// Creates the account and mails a confirmation link. An address that
+/// already has an account gets a heads-up mail instead, and the page says
+/// the same either way, so signing up can't be used to find out who's a
+/// member. For the same reason nobody is logged in here.
 async fn process_signup_form(
     State(app_state): State<AppState>,
     messages: Messages,
     session: Session,
     Form(data): Form<SignupData>
-) -> impl IntoResponse {
-    let mut context = Context::new();
+) -> Result<Response, AppError> {
     if let Err(errors) = data.validate() {
-        context.insert("title", "Sign up");
-        messages.error(format!("{:?}", errors));
-        return Redirect::to("/signup").into_response();
+        return render_signup_form(&app_state, &session, messages, &data, &field_errors(&errors)).await;
     }
-    // Check if email already exists
     let existing_user = User::find()
         .filter(users::Column::Email.eq(data.email.clone()))
         .one(&app_state.db_connection)
-        .await
-        .unwrap();
+        .await?;
 
-    if existing_user.is_some() {
-        context.insert("title", "Sign up");
-        messages.error("Email is already registered.");
-        return Redirect::to("/signup").into_response();
+    // Hash either way so both paths take about as long
+    let password_hash = hash_password(&data.password)?;
+    // Mail goes out in the background, so a slow or failing mail server
+    // doesn't make one path answer later, or with an error
+    let mail_state = app_state.clone();
+    if let Some(user) = existing_user {
+        tokio::spawn(async move {
+            send_already_registered_notice(&mail_state, &user).await;
+        });
+    } else {
+        let new_user = users::ActiveModel {
+            email: Set(data.email.clone()),
+            username: Set(data.username.clone()),
+            password: Set(password_hash),
+            ..Default::default()
+        };
+        let res = new_user.insert(&app_state.db_connection).await?;
+        tokio::spawn(async move {
+            // A failed send can be retried from the settings page after logging in
+            if let Err(err) = send_verification_link(&mail_state, &res).await {
+                eprintln!("{}", err);
+            }
+        });
     }
+    messages.info(format!(
+        "Check your inbox: we sent a mail to {}. Open the link in it to confirm your address, then log in.",
+        data.email,
+    ));
+    Ok(Redirect::to("/login").into_response())
+}
 
-    dotenvy::dotenv().ok();
-    let password_salt = std::env::var("PASSWORD_SALT").expect("PASSWORD_SALT must be set");
-    let user_password = data.password.clone();
-    let user_password_bytes: &[u8] = user_password.as_bytes();
-    let salt = SaltString::from_b64(&password_salt).expect("Invalid base64 salt");
-    let argon2 = Argon2::default();
-    let password_hash = argon2.hash_password(user_password_bytes, &salt).unwrap().to_string();
-    let parsed_hash = PasswordHash::new(&password_hash).unwrap();
-    assert!(Argon2::default().verify_password(user_password_bytes, &parsed_hash).is_ok());
-    let new_user = users::ActiveModel {
-        email: Set(data.email.clone()),
-        username: Set(data.username.clone()),
-        password: Set(password_hash),
-        ..Default::default()
-    };
-    let res = new_user.insert(&app_state.db_connection).await.unwrap();
-    session.insert("user_id", res.id).await.unwrap();
-    let is_logged_in = true;
-    context.insert("is_logged_in", &is_logged_in);
-    messages.info("Hi!");
-    Redirect::to("/").into_response()
-}
\ No newline at end of file
+/// Tells the owner of an address that someone tried to sign up with it.
+async fn send_already_registered_notice(app_state: &AppState, user: &users::Model) {
+    let body = format!(
+        "Hi {},\n\nSomeone tried to sign up for Happening nu with this email address, which already has an account. \
+        If it was you, log in at {}/login, or choose a new password at {}/forgot.\n\n\
+        If it wasn't you, ignore this mail and nothing changes.\n",
+        user.username, app_state.base_url, app_state.base_url,
+    );
+    if let Err(err) = app_state.mailer.send(&user.email, "You already have a Happening nu account", body).await {
+        eprintln!("{}", err);
+    }
+}
+
+/// Renders the signup form, keeping everything typed except the passwords.
+/// Comes back as 422 when there are field errors to show.
+async fn render_signup_form(
+    app_state: &AppState,
+    session: &Session,
+    messages: Messages,
+    form: &SignupData,
+    errors: &HashMap<String, Vec<String>>,
+) -> Result<Response, AppError> {
+    let tera = &app_state.tera;
+    let mut context = Context::new();
+    context.insert("title", "Sign up");
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(session).await?);
+    context.insert("form", &SignupData {
+        email: form.email.clone(),
+        username: form.username.clone(),
+        password: String::new(),
+        confirm_password: String::new(),
+    });
+    context.insert("errors", errors);
+    let html = Html(tera.render("partials/signup.html", &context)?);
+    if errors.is_empty() {
+        Ok(html.into_response())
+    } else {
+        Ok((StatusCode::UNPROCESSABLE_ENTITY, html).into_response())
+    }
+}
@@ -0,0 +1,181 @@
+// External crates
+use axum::{
+    routing::{get, post},
+    extract::{State, Path},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_messages::Messages;
+use chrono::{Duration, Utc};
+use sea_orm::{
+    sea_query::Expr,
+    ActiveModelTrait,
+    ColumnTrait,
+    EntityTrait,
+    QueryFilter,
+    QueryOrder,
+    Set,
+    TransactionTrait,
+};
+use tera::Context;
+use tower_sessions::Session;
+
+// Internal modules
+use crate::AppState;
+use crate::csrf::csrf_token;
+use crate::error::AppError;
+use crate::entities::{ email_verifications, users };
+use crate::entities::email_verifications::Entity as EmailVerification;
+use crate::entities::users::Entity as User;
+use crate::helper::{ get_username_from_session, hash_token, random_token };
+
+/// How long an emailed verification link works.
+const VERIFY_LINK_HOURS: i64 = 24;
+/// The least time between two links, so resending can't flood an inbox.
+const RESEND_AFTER_SECONDS: i64 = 60;
+
+/// Shown when someone who hasn't confirmed their address tries to post.
+pub const UNVERIFIED_EMAIL: &str =
+    "Confirm your email address before posting events. Check your inbox, or send a new link from the settings page.";
+
+pub fn verification_router() -> Router<AppState> {
+    Router::new()
+    .route("/verify/{token}", get(verify_email))
+    .route("/verify/resend", post(resend_verification))
+}
+
+/// Mails `user` a link that confirms their address. Returns whether the
+/// mail went out; a failure is logged and the link can be sent again from
+/// the settings page.
+pub async fn send_verification_link(app_state: &AppState, user: &users::Model) -> Result<bool, AppError> {
+    let token = random_token();
+    let verification = email_verifications::ActiveModel {
+        user_id: Set(user.id),
+        token_hash: Set(hash_token(&token)),
+        expires_at: Set(Utc::now().naive_utc() + Duration::hours(VERIFY_LINK_HOURS)),
+        ..Default::default()
+    };
+    let _ = verification.insert(&app_state.db_connection).await?;
+
+    let body = format!(
+        "Hi {},\n\nConfirm that this is your email address by opening the link below:\n\n{}/verify/{}\n\n\
+        The link works for {} hours. If you didn't sign up for Happening nu, ignore this mail.\n",
+        user.username, app_state.base_url, token, VERIFY_LINK_HOURS,
+    );
+    match app_state.mailer.send(&user.email, "Confirm your email address", body).await {
+        Ok(()) => Ok(true),
+        Err(err) => {
+            eprintln!("{}", err);
+            Ok(false)
+        }
+    }
+}
+
+/// Confirms the address the link was sent to. Works without being logged
+/// in, since the link is often opened on another device.
+pub async fn verify_email(
+    State(app_state): State<AppState>,
+    Path(token): Path<String>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let db = &app_state.db_connection;
+    let now = Utc::now().naive_utc();
+    let verification = EmailVerification::find()
+        .filter(email_verifications::Column::TokenHash.eq(hash_token(&token)))
+        .filter(email_verifications::Column::UsedAt.is_null())
+        .filter(email_verifications::Column::ExpiresAt.gt(now))
+        .one(db)
+        .await?;
+    let user = match verification {
+        Some(verification) => User::find_by_id(verification.user_id).one(db).await?,
+        None => None,
+    };
+    let Some(user) = user else {
+        return render_invalid_link(&app_state, &session, messages).await;
+    };
+
+    let txn = db.begin().await?;
+    let user_id = user.id;
+    if user.email_verified_at.is_none() {
+        let mut active_model: users::ActiveModel = user.into();
+        active_model.email_verified_at = Set(Some(now));
+        let _ = active_model.update(&txn).await?;
+    }
+    // Older links from a resend have nothing left to confirm
+    let _ = EmailVerification::update_many()
+        .col_expr(email_verifications::Column::UsedAt, Expr::value(now))
+        .filter(email_verifications::Column::UserId.eq(user_id))
+        .filter(email_verifications::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+
+    // Signing up doesn't log in, so this is often the way to the login page
+    if session.get::<i32>("user_id").await.unwrap_or(None).is_some() {
+        messages.info("Your email address is confirmed. You can post events now.");
+        Ok(Redirect::to("/").into_response())
+    } else {
+        messages.info("Your email address is confirmed. Log in to start posting events.");
+        Ok(Redirect::to("/login").into_response())
+    }
+}
+
+pub async fn resend_verification(
+    State(app_state): State<AppState>,
+    messages: Messages,
+    session: Session,
+) -> Result<Response, AppError> {
+    let Some(uid) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    let db = &app_state.db_connection;
+    let Some(user) = User::find_by_id(uid).one(db).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    if user.email_verified_at.is_some() {
+        messages.info("Your email address is already confirmed.");
+        return Ok(Redirect::to("/settings").into_response());
+    }
+
+    let latest = EmailVerification::find()
+        .filter(email_verifications::Column::UserId.eq(uid))
+        .order_by_desc(email_verifications::Column::CreatedAt)
+        .one(db)
+        .await?;
+    if let Some(latest) = latest
+        && Utc::now().naive_utc() - latest.created_at < Duration::seconds(RESEND_AFTER_SECONDS)
+    {
+        messages.error("A link was sent a moment ago. Give it a minute, and check your spam folder.");
+        return Ok(Redirect::to("/settings").into_response());
+    }
+
+    if send_verification_link(&app_state, &user).await? {
+        messages.info(format!("A new link is on its way to {}.", user.email));
+    } else {
+        messages.error("The email couldn't be sent. Try again later.");
+    }
+    Ok(Redirect::to("/settings").into_response())
+}
+
+async fn render_invalid_link(
+    app_state: &AppState,
+    session: &Session,
+    messages: Messages,
+) -> Result<Response, AppError> {
+    let mut context = Context::new();
+    let mut info_to_user: Vec<String> = vec![];
+    for msg in messages.into_iter() {
+        info_to_user.push(msg.message);
+    }
+    let logged_in_username = get_username_from_session(session, &app_state.db_connection).await?;
+    context.insert("is_logged_in", &logged_in_username.is_some());
+    context.insert("logged_in_username", &logged_in_username);
+    context.insert("not_home", &true);
+    context.insert("messages", &info_to_user);
+    context.insert("csrf_token", &csrf_token(session).await?);
+    context.insert("title", "Confirm your email address");
+    let html = Html(app_state.tera.render("partials/verify_email.html", &context)?);
+    Ok((StatusCode::NOT_FOUND, html).into_response())
+}
@@ -1,19 +1,153 @@
-use sea_orm::EntityTrait;
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore}, PasswordHash, PasswordHasher, PasswordVerifier, SaltString
+    },
+    Argon2
+};
+use sea_orm::{
+    sea_query::{Expr, Func},
+    ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use validator::ValidationErrors;
 use tower_sessions::Session;
+use crate::auth::Role;
+use crate::entities::{categories, locations, users};
 use crate::entities::users::Entity as User;
+use crate::error::AppError;
+use crate::handler::models::EventChoices;
 
-pub async fn get_username_from_session(session: &Session, db: &sea_orm::DatabaseConnection) -> Option<String> {
+pub async fn get_username_from_session(session: &Session, db: &sea_orm::DatabaseConnection) -> Result<Option<String>, AppError> {
     if let Some(user_id) = session.get::<i32>("user_id").await.unwrap_or(None) {
         if let Some(user) = User::find_by_id(user_id)
             .one(db)
-            .await
-            .unwrap()
+            .await?
         {
-            Some(user.username.clone())
+            Ok(Some(user.username.clone()))
         } else {
-            None
+            Ok(None)
         }
     } else {
-        None
+        Ok(None)
     }
+}
+
+/// Location and category names in the order they were added. Retired ones
+/// are left out unless `include_retired`, which filters over old events need.
+pub async fn event_choices(db: &DatabaseConnection, include_retired: bool) -> Result<EventChoices, DbErr> {
+    let mut location_query = locations::Entity::find().order_by_asc(locations::Column::Id);
+    let mut category_query = categories::Entity::find().order_by_asc(categories::Column::Id);
+    if !include_retired {
+        location_query = location_query.filter(locations::Column::RetiredAt.is_null());
+        category_query = category_query.filter(categories::Column::RetiredAt.is_null());
+    }
+    Ok(EventChoices {
+        locations: location_query.all(db).await?.into_iter().map(|l| l.name).collect(),
+        categories: category_query.all(db).await?.into_iter().map(|c| c.name).collect(),
+    })
+}
+
+/// The logged in user's role, `Role::User` when nobody is logged in.
+pub async fn get_role_from_session(session: &Session, db: &DatabaseConnection) -> Result<Role, AppError> {
+    let Some(user_id) = session.get::<i32>("user_id").await.unwrap_or(None) else {
+        return Ok(Role::User);
+    };
+    Ok(User::find_by_id(user_id)
+        .one(db)
+        .await?
+        .map(|user| Role::of(&user))
+        .unwrap_or(Role::User))
+}
+
+/// Whether the user has confirmed their email address, which posting
+/// events waits for.
+pub async fn email_verified(db: &DatabaseConnection, user_id: i32) -> Result<bool, DbErr> {
+    Ok(User::find_by_id(user_id)
+        .one(db)
+        .await?
+        .is_some_and(|user| user.email_verified_at.is_some()))
+}
+
+/// Makes the accounts listed in the comma separated `ADMIN_EMAILS` admins,
+/// which is how a fresh install gets its first one. Run at startup.
+pub async fn promote_listed_admins(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let emails: Vec<String> = std::env::var("ADMIN_EMAILS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|email| email.trim().to_lowercase())
+        .filter(|email| !email.is_empty())
+        .collect();
+    if emails.is_empty() {
+        return Ok(());
+    }
+    let _ = User::update_many()
+        .col_expr(users::Column::Role, Expr::value(Role::Admin.as_str()))
+        .filter(Expr::expr(Func::lower(Expr::col(users::Column::Email))).is_in(emails))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// 32 random bytes from the OS RNG as hex, for CSRF and URL tokens.
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SHA-256 of a random token as hex, which is what `api_tokens`,
+/// `password_resets`, `email_verifications` and `recovery_codes` store. The
+/// tokens are random, so a slow password hash would buy nothing here.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hashes a password with a fresh random salt. The salt is stored inside the
+/// returned PHC string, so verification needs nothing but `users.password`.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Whether `password` matches a stored PHC string from `hash_password`.
+pub fn password_matches(password: &str, hash: &str) -> Result<bool, argon2::password_hash::Error> {
+    let parsed_hash = PasswordHash::new(hash)?;
+    Ok(Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_ok())
+}
+
+/// A hash of nothing in particular, made with the same parameters as real
+/// ones, for `waste_password_check` to verify against.
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    hash_password("not anyone's password").expect("hashing a constant can't fail")
+});
+
+/// Spends as long as checking a real password would, for paths that have no
+/// account to check against. Otherwise how quickly a login fails would tell
+/// whether the email has an account.
+pub fn waste_password_check(password: &str) {
+    let _ = password_matches(password, &DUMMY_HASH);
+}
+
+/// Flattens `validator` errors into field name -> messages for the templates,
+/// falling back to the error code when a rule has no message.
+pub fn field_errors(errors: &ValidationErrors) -> HashMap<String, Vec<String>> {
+    errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let messages = errors
+                .iter()
+                .map(|error| match &error.message {
+                    Some(message) => message.to_string(),
+                    None => error.code.to_string(),
+                })
+                .collect();
+            (field.to_string(), messages)
+        })
+        .collect()
 }
\ No newline at end of file
@@ -0,0 +1,96 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    AsyncFileTransport,
+    AsyncSmtpTransport,
+    AsyncTransport,
+    Message,
+    Tokio1Executor,
+};
+
+/// Where outgoing mail goes, picked by `MAIL_TRANSPORT`. `file`, the default,
+/// writes every message as an `.eml` file into `MAIL_DIR`. `smtp` hands it
+/// to a plain SMTP server at `SMTP_HOST`:`SMTP_PORT` without TLS or login,
+/// meant for a local relay or a test sink such as Mailpit.
+#[derive(Clone)]
+pub struct Mailer {
+    from: Mailbox,
+    transport: Transport,
+}
+
+#[derive(Clone)]
+enum Transport {
+    File(PathBuf),
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+}
+
+#[derive(Debug)]
+pub struct MailError(String);
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mail error: {}", self.0)
+    }
+}
+
+impl Mailer {
+    pub fn from_env() -> Result<Self, MailError> {
+        let from = std::env::var("MAIL_FROM")
+            .unwrap_or_else(|_| String::from("Happening nu <noreply@localhost>"))
+            .parse::<Mailbox>()
+            .map_err(|err| MailError(format!("MAIL_FROM: {}", err)))?;
+
+        let transport = match std::env::var("MAIL_TRANSPORT").as_deref() {
+            Ok("smtp") => {
+                let host = std::env::var("SMTP_HOST").unwrap_or_else(|_| String::from("localhost"));
+                let port = std::env::var("SMTP_PORT")
+                    .ok()
+                    .and_then(|port| port.parse().ok())
+                    .unwrap_or(1025);
+                Transport::Smtp(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host).port(port).build())
+            }
+            Ok("file") | Err(_) => {
+                Transport::File(PathBuf::from(std::env::var("MAIL_DIR").unwrap_or_else(|_| String::from("mail"))))
+            }
+            Ok(other) => return Err(MailError(format!("unknown MAIL_TRANSPORT \"{}\"", other))),
+        };
+        Ok(Mailer { from, transport })
+    }
+
+    /// File mailer writing into `dir`; tests only.
+    #[cfg(test)]
+    pub fn to_dir(dir: PathBuf) -> Self {
+        Mailer {
+            from: "Happening nu <noreply@localhost>".parse().unwrap(),
+            transport: Transport::File(dir),
+        }
+    }
+
+    /// Sends a plain text message.
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), MailError> {
+        let to = to.parse::<Mailbox>().map_err(|err| MailError(err.to_string()))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|err| MailError(err.to_string()))?;
+
+        match &self.transport {
+            Transport::File(dir) => {
+                tokio::fs::create_dir_all(dir).await.map_err(|err| MailError(err.to_string()))?;
+                AsyncFileTransport::<Tokio1Executor>::new(dir)
+                    .send(message)
+                    .await
+                    .map_err(|err| MailError(err.to_string()))?;
+            }
+            Transport::Smtp(smtp) => {
+                smtp.send(message).await.map_err(|err| MailError(err.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+}
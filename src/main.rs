@@ -1,7 +1,8 @@
 // Standard library imports
+use std::net::SocketAddr;
 
 // External crates
-use axum::Router;
+use axum::{middleware, Router};
 use axum_messages::MessagesManagerLayer;
 use sea_orm::{Database, DatabaseConnection};
 use tera::Tera;
@@ -12,10 +13,17 @@ use tower_sessions::{session_store::ExpiredDeletion, Expiry, SessionManagerLayer
 use tower_sessions_sqlx_store::{sqlx::SqlitePool, SqliteStore};
 
 // Internal modules
+mod auth;
+mod csrf;
 mod entities;
+mod error;
 mod handler;
 mod router;
 mod helper;
+mod mailer;
+mod openapi;
+mod throttle;
+mod totp;
 
 // Internal crates
 use crate::router::routes;
@@ -24,6 +32,12 @@ use crate::router::routes;
 struct AppState {
     db_connection: DatabaseConnection,
     tera: Tera,
+    base_url: String,
+    /// Flags it takes to hide an event until a moderator looks at it
+    flag_threshold: u64,
+    mailer: mailer::Mailer,
+    /// Whether `X-Forwarded-For` comes from our own reverse proxy
+    trust_proxy: bool,
 }
 
 #[tokio::main]
@@ -32,6 +46,9 @@ async fn main() {
 
     let database_url: String = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set as an environment variable.");
     let dbconnection: DatabaseConnection = Database::connect(&database_url).await.unwrap();
+    helper::promote_listed_admins(&dbconnection).await.unwrap();
+    // Make the dummy hash now rather than during the first unknown-email login
+    helper::waste_password_check("");
 
     let dbpool = SqlitePool::connect(&database_url).await.unwrap();
     let session_store = SqliteStore::new(dbpool);
@@ -48,22 +65,54 @@ async fn main() {
         .with_secure(false)
         .with_expiry(Expiry::OnInactivity(TimeDuration::hours(1)));
 
+    // Absolute links in calendar files need to know where the site lives
+    let base_url: String = std::env::var("BASE_URL").unwrap_or_else(|_| String::from("http://localhost:3000"));
+
+    let flag_threshold: u64 = std::env::var("FLAG_HIDE_THRESHOLD")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3);
+
+    let mailer = mailer::Mailer::from_env().expect("mail settings are invalid");
+
+    let trust_proxy = matches!(std::env::var("TRUST_PROXY").as_deref(), Ok("1") | Ok("true"));
+
+    // Counters of failed logins nobody has touched for a day
+    let throttle_db = dbconnection.clone();
+    let _forget_task = tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(TokioDuration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(err) = throttle::forget_old(&throttle_db, chrono::Utc::now().naive_utc()).await {
+                eprintln!("could not forget old login failures: {}", err);
+            }
+        }
+    });
+
     let tera_templates: Tera = Tera::new("templates/*.html").unwrap();
 
     let app_state: AppState = AppState {
         db_connection: dbconnection,
         tera: tera_templates,
+        base_url: base_url.trim_end_matches('/').to_string(),
+        flag_threshold,
+        mailer,
+        trust_proxy,
     };
 
     let assets_dir = ServeDir::new("assets").not_found_service(ServeFile::new("assets/index.html"));
 
     let app: Router = routes()
+        .fallback(error::not_found)
+        .layer(middleware::from_fn(csrf::verify_csrf_token))
+        .layer(middleware::from_fn_with_state(app_state.clone(), auth::end_stale_sessions))
+        .layer(middleware::from_fn_with_state(app_state.clone(), error::render_error_page))
         .layer(MessagesManagerLayer)
         .layer(session_layer)
         .nest_service("/assets", assets_dir.clone())
         .with_state(app_state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 
 }
@@ -0,0 +1,1028 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::handler::models::{
+    ApiEvent,
+    ApiEventPage,
+    ApiRsvp,
+    ApiUser,
+    ChoiceNameData,
+    EventFilter,
+    EventListItem,
+    ForgotPasswordData,
+    LoginData,
+    ModerationData,
+    NewApiTokenData,
+    NewEventData,
+    NewFlagData,
+    Pagination,
+    PasswordCheckData,
+    ResetPasswordData,
+    SearchQuery,
+    SignupData,
+    TotpCodeData,
+    UserRoleData,
+};
+
+/// A type handlers take or return, documented through an example value. The
+/// schema is generated from how the example serializes, so adding or
+/// renaming a field changes the spec with it, and a new field won't compile
+/// until the example has it.
+pub trait ApiSchema: Serialize {
+    fn example() -> Self;
+
+    /// Fields that may be left out or null, which one example can't show.
+    fn optional_fields() -> &'static [&'static str] {
+        &[]
+    }
+
+    fn schema() -> Value
+    where
+        Self: Sized,
+    {
+        let example = serde_json::to_value(Self::example()).unwrap_or_default();
+        schema_from_example(&example, Self::optional_fields())
+    }
+}
+
+fn schema_from_example(example: &Value, optional: &[&str]) -> Value {
+    match example {
+        Value::Null => json!({ "nullable": true }),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(number) if number.is_f64() => json!({ "type": "number" }),
+        Value::Number(_) => json!({ "type": "integer" }),
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(items) => json!({
+            "type": "array",
+            "items": items.first().map(|item| schema_from_example(item, &[])).unwrap_or_default(),
+        }),
+        Value::Object(fields) => {
+            let mut properties = Map::new();
+            let mut required = Vec::new();
+            for (name, value) in fields {
+                let mut property = schema_from_example(value, &[]);
+                if optional.contains(&name.as_str()) {
+                    property["nullable"] = json!(true);
+                } else {
+                    required.push(name.clone());
+                }
+                property["example"] = value.clone();
+                properties.insert(name.clone(), property);
+            }
+            json!({ "type": "object", "properties": properties, "required": required })
+        }
+    }
+}
+
+impl ApiSchema for NewEventData {
+    fn example() -> Self {
+        NewEventData {
+            title: String::from("Fika meetup"),
+            url: String::from("https://example.com/fika"),
+            location: String::from("Malmö"),
+            date: String::from("2026-12-05"),
+            category: String::from("Social"),
+            description: String::from("Coffee and cinnamon buns."),
+        }
+    }
+
+    fn optional_fields() -> &'static [&'static str] {
+        &["description"]
+    }
+}
+
+impl ApiSchema for SignupData {
+    fn example() -> Self {
+        SignupData {
+            email: String::from("alice@example.com"),
+            username: String::from("alice"),
+            password: String::from("secret12"),
+            confirm_password: String::from("secret12"),
+        }
+    }
+}
+
+impl ApiSchema for LoginData {
+    fn example() -> Self {
+        LoginData {
+            email: String::from("alice@example.com"),
+            password: String::from("secret12"),
+        }
+    }
+}
+
+impl ApiSchema for ForgotPasswordData {
+    fn example() -> Self {
+        ForgotPasswordData { email: String::from("alice@example.com") }
+    }
+}
+
+impl ApiSchema for ResetPasswordData {
+    fn example() -> Self {
+        ResetPasswordData {
+            password: String::from("secret34"),
+            confirm_password: String::from("secret34"),
+        }
+    }
+}
+
+impl ApiSchema for TotpCodeData {
+    fn example() -> Self {
+        TotpCodeData { code: String::from("492039") }
+    }
+}
+
+impl ApiSchema for PasswordCheckData {
+    fn example() -> Self {
+        PasswordCheckData { password: String::from("secret12") }
+    }
+}
+
+impl ApiSchema for NewApiTokenData {
+    fn example() -> Self {
+        NewApiTokenData { name: String::from("import script") }
+    }
+}
+
+impl ApiSchema for ChoiceNameData {
+    fn example() -> Self {
+        ChoiceNameData { name: String::from("Lund") }
+    }
+}
+
+impl ApiSchema for NewFlagData {
+    fn example() -> Self {
+        NewFlagData {
+            reason: String::from("Fake or broken link"),
+            comment: String::from("The link goes to a shop."),
+        }
+    }
+
+    fn optional_fields() -> &'static [&'static str] {
+        &["comment"]
+    }
+}
+
+impl ApiSchema for UserRoleData {
+    fn example() -> Self {
+        UserRoleData { role: String::from("moderator") }
+    }
+}
+
+impl ApiSchema for ModerationData {
+    fn example() -> Self {
+        ModerationData { back: Some(String::from("/admin/events?page=2")) }
+    }
+
+    fn optional_fields() -> &'static [&'static str] {
+        &["back"]
+    }
+}
+
+impl ApiSchema for EventFilter {
+    fn example() -> Self {
+        EventFilter {
+            location: Some(String::from("Malmö")),
+            category: Some(String::from("Social")),
+            from: Some(String::from("2026-12-01")),
+            to: Some(String::from("2026-12-31")),
+        }
+    }
+
+    fn optional_fields() -> &'static [&'static str] {
+        &["location", "category", "from", "to"]
+    }
+}
+
+impl ApiSchema for Pagination {
+    fn example() -> Self {
+        Pagination { page: 2 }
+    }
+
+    fn optional_fields() -> &'static [&'static str] {
+        &["page"]
+    }
+}
+
+impl ApiSchema for SearchQuery {
+    fn example() -> Self {
+        SearchQuery { q: String::from("fika malmö") }
+    }
+
+    fn optional_fields() -> &'static [&'static str] {
+        &["q"]
+    }
+}
+
+impl ApiSchema for EventListItem {
+    fn example() -> Self {
+        EventListItem {
+            id: 1,
+            title: String::from("Fika meetup"),
+            url: String::from("https://example.com/fika"),
+            location: String::from("Malmö"),
+            date: String::from("2026-12-05"),
+            category: String::from("Social"),
+            username: String::from("alice"),
+            attendee_count: 3,
+            is_going: false,
+        }
+    }
+}
+
+impl ApiSchema for ApiEvent {
+    fn example() -> Self {
+        ApiEvent {
+            id: 1,
+            title: String::from("Fika meetup"),
+            url: String::from("https://example.com/fika"),
+            location: String::from("Malmö"),
+            date: String::from("2026-12-05"),
+            category: String::from("Social"),
+            description: String::from("Coffee and cinnamon buns."),
+            username: String::from("alice"),
+            attendee_count: 3,
+            is_going: false,
+            created_at: String::from("2026-10-17T12:00:00+00:00"),
+            updated_at: Some(String::from("2026-10-18T09:30:00+00:00")),
+        }
+    }
+
+    fn optional_fields() -> &'static [&'static str] {
+        &["updated_at"]
+    }
+}
+
+impl ApiSchema for ApiEventPage {
+    fn example() -> Self {
+        ApiEventPage {
+            events: vec![EventListItem::example()],
+            page: 1,
+            num_pages: 3,
+        }
+    }
+}
+
+impl ApiSchema for ApiRsvp {
+    fn example() -> Self {
+        ApiRsvp { event_id: 1, is_going: true, attendee_count: 4 }
+    }
+}
+
+impl ApiSchema for ApiUser {
+    fn example() -> Self {
+        ApiUser {
+            id: 1,
+            username: String::from("alice"),
+            email: String::from("alice@example.com"),
+            csrf_token: Some(String::from("3f2a...")),
+        }
+    }
+
+    fn optional_fields() -> &'static [&'static str] {
+        &["csrf_token"]
+    }
+}
+
+/// The body of every `ApiError` response.
+fn error_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "error": {
+                "type": "object",
+                "properties": {
+                    "code": {
+                        "type": "string",
+                        "enum": ["bad_request", "unauthorized", "forbidden", "not_found", "validation_failed", "internal_error"],
+                    },
+                    "message": { "type": "string" },
+                    "fields": {
+                        "type": "object",
+                        "description": "Field name to messages, only filled in for validation_failed",
+                        "additionalProperties": { "type": "array", "items": { "type": "string" } },
+                        "example": { "title": ["event title should be between 4 to 30 characters."] },
+                    },
+                },
+                "required": ["code", "message", "fields"],
+            },
+        },
+        "required": ["error"],
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+/// A form body for a `<form method="POST">`, which also carries the CSRF token.
+fn form_body<T: ApiSchema>() -> Value {
+    let mut schema = T::schema();
+    schema["properties"]["csrf_token"] = json!({ "type": "string", "description": "The session's CSRF token" });
+    if let Some(required) = schema["required"].as_array_mut() {
+        required.push(json!("csrf_token"));
+    }
+    json!({ "required": true, "content": { "application/x-www-form-urlencoded": { "schema": schema } } })
+}
+
+fn json_body(schema: &str) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema_ref(schema) } } })
+}
+
+/// Query parameters, one per field of `T`.
+fn query_params<T: ApiSchema>(only: Option<&[&str]>) -> Vec<Value> {
+    let schema = T::schema();
+    let required = schema["required"].as_array().cloned().unwrap_or_default();
+    let Some(properties) = schema["properties"].as_object() else {
+        return Vec::new();
+    };
+    properties
+        .iter()
+        .filter(|(name, _)| only.is_none_or(|only| only.contains(&name.as_str())))
+        .map(|(name, property)| json!({
+            "name": name,
+            "in": "query",
+            "required": required.contains(&json!(name)),
+            "schema": { "type": property["type"] },
+            "example": property["example"],
+        }))
+        .collect()
+}
+
+fn html(description: &str) -> Value {
+    json!({ "description": description, "content": { "text/html": { "schema": { "type": "string" } } } })
+}
+
+fn json_response(description: &str, schema: &str) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema_ref(schema) } } })
+}
+
+fn api_error(description: &str) -> Value {
+    json_response(description, "ApiError")
+}
+
+fn redirect(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+fn text(content_type: &str, description: &str) -> Value {
+    json!({ "description": description, "content": { content_type: { "schema": { "type": "string" } } } })
+}
+
+/// One documented route.
+pub struct Operation {
+    pub method: &'static str,
+    pub path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    description: Option<&'static str>,
+    parameters: Vec<Value>,
+    request_body: Option<Value>,
+    responses: Vec<(&'static str, Value)>,
+    api: bool,
+}
+
+impl Operation {
+    fn new(method: &'static str, path: &'static str, tag: &'static str, summary: &'static str) -> Self {
+        Operation {
+            method,
+            path,
+            tag,
+            summary,
+            description: None,
+            parameters: Vec::new(),
+            request_body: None,
+            responses: Vec::new(),
+            api: false,
+        }
+    }
+
+    fn description(mut self, description: &'static str) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    fn params(mut self, parameters: Vec<Value>) -> Self {
+        self.parameters.extend(parameters);
+        self
+    }
+
+    fn body(mut self, body: Value) -> Self {
+        self.request_body = Some(body);
+        self
+    }
+
+    fn response(mut self, status: &'static str, response: Value) -> Self {
+        self.responses.push((status, response));
+        self
+    }
+
+    /// Part of `/api/v1`: token or cookie auth, JSON errors.
+    fn api(mut self) -> Self {
+        self.api = true;
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut parameters: Vec<Value> = self.parameters.clone();
+        // Path parameters come straight from the route, `{id}` is always an integer
+        for name in path_params(self.path) {
+            if parameters.iter().any(|p| p["name"] == name && p["in"] == "path") {
+                continue;
+            }
+            let kind = if name == "id" { "integer" } else { "string" };
+            parameters.push(json!({ "name": name, "in": "path", "required": true, "schema": { "type": kind } }));
+        }
+
+        let mut operation = json!({
+            "tags": [self.tag],
+            "summary": self.summary,
+            "operationId": format!("{}_{}", self.method, operation_name(self.path)),
+            "responses": self.responses
+                .iter()
+                .map(|(status, response)| (status.to_string(), response.clone()))
+                .collect::<Map<String, Value>>(),
+        });
+        if let Some(description) = self.description {
+            operation["description"] = json!(description);
+        }
+        if !parameters.is_empty() {
+            operation["parameters"] = json!(parameters);
+        }
+        if let Some(body) = &self.request_body {
+            operation["requestBody"] = body.clone();
+        }
+        if self.api {
+            // Endpoints that never answer 401 also work without credentials
+            let mut security = vec![json!({ "bearerAuth": [] }), json!({ "sessionCookie": [] })];
+            if !self.responses.iter().any(|(status, _)| *status == "401") {
+                security.push(json!({}));
+            }
+            operation["security"] = json!(security);
+        }
+        operation
+    }
+}
+
+/// The `{list}` of the admin pages, `locations` or `categories`.
+fn choice_list_param() -> Vec<Value> {
+    vec![json!({
+        "name": "list",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string", "enum": ["locations", "categories"] },
+    })]
+}
+
+fn emailed_token_param() -> Vec<Value> {
+    vec![json!({
+        "name": "token",
+        "in": "path",
+        "required": true,
+        "description": "The token from the emailed link",
+        "schema": { "type": "string" },
+    })]
+}
+
+fn path_params(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(String::from)
+        .collect()
+}
+
+fn operation_name(path: &str) -> String {
+    let name: String = path
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let name = name.trim_matches('_').replace("__", "_");
+    if name.is_empty() { String::from("root") } else { name }
+}
+
+/// Every route in `router::routes`. The tests below fail when this and the
+/// routers disagree.
+pub fn operations() -> Vec<Operation> {
+    let not_logged_in = "Not logged in, redirects to /login";
+    vec![
+        // user_handler
+        Operation::new("get", "/login", "Users", "Login form")
+            .response("200", html("The login form"))
+            .response("303", redirect("Already logged in, redirects to /")),
+        Operation::new("post", "/login", "Users", "Log in")
+            .body(form_body::<LoginData>())
+            .description("An unknown email and a wrong password get the same message, after the same amount of work. \
+                Accounts with two-factor authentication on continue at /login/2fa before they are logged in. \
+                Failed logins are counted per email and per client address. After a few, attempts have to wait \
+                a doubling delay, and ten in a row lock the email out for 15 minutes.")
+            .response("303", redirect("Redirects to / (or /login/2fa) when the password matches, else back to /login with a message")),
+        Operation::new("get", "/logout", "Users", "Log out")
+            .response("303", redirect("Redirects to /")),
+        Operation::new("get", "/signup", "Users", "Signup form")
+            .response("200", html("The signup form"))
+            .response("303", redirect("Already logged in, redirects to /")),
+        Operation::new("post", "/signup", "Users", "Create an account")
+            .body(form_body::<SignupData>())
+            .description("Mails a link that confirms the address, and posting events waits until it's opened. \
+                An address that already has an account is mailed a notice instead, and the response is the same, \
+                so it doesn't tell who is a member.")
+            .response("303", redirect("Redirects to /login with a message to check the inbox"))
+            .response("422", html("The form again with a message per invalid field")),
+
+        // password_handler
+        Operation::new("get", "/forgot", "Users", "Forgotten password form")
+            .response("200", html("Asks for the account's email")),
+        Operation::new("post", "/forgot", "Users", "Mail a password reset link")
+            .description("The link works once, for an hour. The answer is the same whether or not the email has an account.")
+            .body(form_body::<ForgotPasswordData>())
+            .response("303", redirect("Redirects to /login with a message")),
+        Operation::new("get", "/reset/{token}", "Users", "New password form")
+            .params(emailed_token_param())
+            .response("200", html("The new password form"))
+            .response("404", html("Unknown, expired or used link")),
+        Operation::new("post", "/reset/{token}", "Users", "Set a new password")
+            .description("Uses up every reset link the account has and logs it out everywhere.")
+            .params(emailed_token_param())
+            .body(form_body::<ResetPasswordData>())
+            .response("303", redirect("Redirects to /login"))
+            .response("404", html("Unknown, expired or used link"))
+            .response("422", html("The form again with a message per invalid field")),
+
+        // verification_handler
+        Operation::new("get", "/verify/{token}", "Users", "Confirm an email address")
+            .description("Works without being logged in.")
+            .params(emailed_token_param())
+            .response("303", redirect("Confirmed, redirects to /, or /login when not logged in"))
+            .response("404", html("Unknown, expired or used link")),
+        Operation::new("post", "/verify/resend", "Users", "Mail a new confirmation link")
+            .description("At most one link a minute.")
+            .response("303", redirect("Redirects to /settings with a message, or /login when not logged in")),
+
+        // event_handler
+        Operation::new("get", "/", "Events", "Upcoming events")
+            .description("With an `HX-Request` header only the rows are returned, for the htmx \"More\" link.")
+            .params(query_params::<EventFilter>(None))
+            .params(query_params::<Pagination>(None))
+            .response("200", html("Soonest first, 30 per page")),
+        Operation::new("get", "/archive", "Events", "Past events")
+            .params(query_params::<EventFilter>(None))
+            .params(query_params::<Pagination>(None))
+            .response("200", html("Newest first, 30 per page")),
+        Operation::new("get", "/user_events", "Events", "Events I created")
+            .response("200", html("The events with edit and delete buttons"))
+            .response("303", redirect(not_logged_in)),
+        Operation::new("get", "/agenda", "Events", "Upcoming events I'm going to, by week")
+            .response("200", html("The agenda with the calendar subscription link"))
+            .response("303", redirect(not_logged_in)),
+        Operation::new("get", "/new_event", "Events", "New event form")
+            .response("200", html("The event form"))
+            .response("303", redirect("Redirects to /login when not logged in, or /settings when the email isn't confirmed")),
+        Operation::new("post", "/new_event", "Events", "Create an event")
+            .body(form_body::<NewEventData>())
+            .response("303", redirect("Created, redirects to /, or to /settings when the email isn't confirmed"))
+            .response("422", html("The form again with a message per invalid field")),
+        Operation::new("get", "/event/{id}", "Events", "Event page")
+            .description("`/event/{id}.ics` returns the event as an iCalendar file instead.")
+            .params(vec![json!({
+                "name": "id",
+                "in": "path",
+                "required": true,
+                "description": "The event id, optionally followed by `.ics`",
+                "schema": { "type": "string" },
+                "example": "1.ics",
+            })])
+            .response("200", json!({
+                "description": "The event page, or its iCalendar file",
+                "content": {
+                    "text/html": { "schema": { "type": "string" } },
+                    "text/calendar": { "schema": { "type": "string" } },
+                },
+            }))
+            .response("404", html("No such event")),
+        Operation::new("get", "/event/{id}/edit", "Events", "Edit event form")
+            .response("200", html("The event form, filled in"))
+            .response("303", redirect("Not logged in or not the creator"))
+            .response("404", html("No such event")),
+        Operation::new("post", "/event/{id}/edit", "Events", "Update an event")
+            .body(form_body::<NewEventData>())
+            .response("303", redirect("Saved, redirects to the event page"))
+            .response("404", html("No such event"))
+            .response("422", html("The form again with a message per invalid field")),
+        Operation::new("post", "/event/{id}/delete", "Events", "Delete an event")
+            .body(json!({ "content": { "application/x-www-form-urlencoded": { "schema": {
+                "type": "object",
+                "properties": { "csrf_token": { "type": "string" } },
+                "required": ["csrf_token"],
+            } } } }))
+            .response("303", redirect("Redirects to /user_events"))
+            .response("404", html("No such event")),
+        Operation::new("post", "/api/event/{id}/going", "Events", "Toggle going (htmx)")
+            .description("Sent by the going button with the CSRF token in the `X-CSRF-Token` header. Use `/api/v1/events/{id}/rsvp` for JSON.")
+            .response("200", html("The button and attendee count, to swap in"))
+            .response("404", html("No such event")),
+
+        // calendar_handler
+        Operation::new("get", "/calendar/{feed}", "Calendar", "Calendar subscription")
+            .params(vec![json!({
+                "name": "feed",
+                "in": "path",
+                "required": true,
+                "description": "The user's calendar token followed by `.ics`",
+                "schema": { "type": "string" },
+            })])
+            .response("200", text("text/calendar", "Every event the user is going to"))
+            .response("404", html("Unknown or replaced token")),
+        Operation::new("post", "/calendar/reset", "Calendar", "Replace the calendar token")
+            .response("303", redirect("Redirects to /agenda")),
+
+        // feed_handler
+        Operation::new("get", "/feed.rss", "Feeds", "RSS feed of new events")
+            .params(query_params::<EventFilter>(Some(&["location", "category"])))
+            .response("200", text("application/rss+xml", "The 50 newest events")),
+        Operation::new("get", "/feed.atom", "Feeds", "Atom feed of new events")
+            .params(query_params::<EventFilter>(Some(&["location", "category"])))
+            .response("200", text("application/atom+xml", "The 50 newest events")),
+
+        // search_handler
+        Operation::new("get", "/search", "Events", "Search events")
+            .description("Matches words in titles, categories and locations. With an `HX-Request` header only the rows are returned, for the navbar's live search.")
+            .params(query_params::<SearchQuery>(None))
+            .response("200", html("Best matches first, matched words in <mark>")),
+
+        // settings_handler
+        Operation::new("get", "/settings", "Settings", "API tokens")
+            .response("200", html("The user's API tokens"))
+            .response("303", redirect(not_logged_in)),
+        Operation::new("post", "/settings/tokens", "Settings", "Create an API token")
+            .body(form_body::<NewApiTokenData>())
+            .response("200", html("The settings page showing the new token once"))
+            .response("422", html("The form again with a message")),
+        Operation::new("post", "/settings/tokens/{id}/delete", "Settings", "Revoke an API token")
+            .response("303", redirect("Redirects to /settings"))
+            .response("404", html("No such token")),
+
+        // two_factor_handler
+        Operation::new("get", "/login/2fa", "Users", "Second login step")
+            .response("200", html("Asks for an authenticator or recovery code"))
+            .response("303", redirect("No password was checked first, redirects to /login")),
+        Operation::new("post", "/login/2fa", "Users", "Finish logging in with a code")
+            .description("Takes a current authenticator code or an unused recovery code. Five wrong codes, or five minutes, and the password has to be entered again. \
+                Wrong codes count as failed logins.")
+            .body(form_body::<TotpCodeData>())
+            .response("303", redirect("Logged in, redirects to /, or back to /login"))
+            .response("422", html("The form again with a message")),
+        Operation::new("get", "/settings/2fa", "Settings", "Two-factor settings")
+            .response("200", html("A QR code to set up an authenticator, or the recovery code and disable forms"))
+            .response("303", redirect(not_logged_in)),
+        Operation::new("post", "/settings/2fa/enable", "Settings", "Turn on two-factor authentication")
+            .description("The code has to come from the secret on the setup page. Answers with the first recovery codes, shown once.")
+            .body(form_body::<TotpCodeData>())
+            .response("200", html("The settings page with the recovery codes"))
+            .response("303", redirect(not_logged_in))
+            .response("422", html("The setup page again with a message")),
+        Operation::new("post", "/settings/2fa/recovery", "Settings", "Replace the recovery codes")
+            .body(form_body::<PasswordCheckData>())
+            .response("200", html("The settings page with the new codes, shown once"))
+            .response("303", redirect(not_logged_in))
+            .response("422", html("Wrong password")),
+        Operation::new("post", "/settings/2fa/disable", "Settings", "Turn off two-factor authentication")
+            .body(form_body::<PasswordCheckData>())
+            .response("303", redirect("Redirects to /settings/2fa"))
+            .response("422", html("Wrong password")),
+
+        // admin_handler
+        Operation::new("get", "/admin", "Admin", "Dashboard")
+            .description("Moderators and admins. Admins also see the newest accounts.")
+            .response("200", html("Counts of users and events, and recent signups"))
+            .response("303", redirect(not_logged_in))
+            .response("403", html("Not a moderator")),
+        Operation::new("get", "/admin/users", "Admin", "All users, newest first")
+            .params(query_params::<Pagination>(None))
+            .response("200", html("One page of users with their role and event count"))
+            .response("303", redirect(not_logged_in))
+            .response("403", html("Not an admin")),
+        Operation::new("post", "/admin/users/{id}/role", "Admin", "Change a user's role")
+            .body(form_body::<UserRoleData>())
+            .response("303", redirect("Redirects to /admin/users"))
+            .response("403", html("Not an admin"))
+            .response("404", html("No such user")),
+        Operation::new("post", "/admin/users/{id}/ban", "Admin", "Ban a user")
+            .description("Logs them out and stops their API tokens working. Admins can't be banned.")
+            .body(form_body::<ModerationData>())
+            .response("303", redirect("Redirects to `back`, or /admin/users"))
+            .response("403", html("Not an admin"))
+            .response("404", html("No such user")),
+        Operation::new("post", "/admin/users/{id}/unban", "Admin", "Lift a ban")
+            .body(form_body::<ModerationData>())
+            .response("303", redirect("Redirects to `back`, or /admin/users"))
+            .response("403", html("Not an admin"))
+            .response("404", html("No such user")),
+        Operation::new("get", "/admin/events", "Admin", "All events, hidden ones included, newest first")
+            .params(query_params::<Pagination>(None))
+            .response("200", html("One page of events with moderation buttons"))
+            .response("303", redirect(not_logged_in))
+            .response("403", html("Not a moderator")),
+        Operation::new("post", "/admin/events/{id}/hide", "Admin", "Hide an event")
+            .description("Hidden events are left out of listings, search, feeds and calendars.")
+            .body(form_body::<ModerationData>())
+            .response("303", redirect("Redirects to `back`, or the event"))
+            .response("403", html("Not a moderator"))
+            .response("404", html("No such event")),
+        Operation::new("post", "/admin/events/{id}/unhide", "Admin", "Show a hidden event again")
+            .body(form_body::<ModerationData>())
+            .response("303", redirect("Redirects to `back`, or the event"))
+            .response("403", html("Not a moderator"))
+            .response("404", html("No such event")),
+        Operation::new("post", "/admin/events/{id}/delete", "Admin", "Delete any event")
+            .body(form_body::<ModerationData>())
+            .response("303", redirect("Redirects to `back`, or /admin/events"))
+            .response("403", html("Not a moderator"))
+            .response("404", html("No such event")),
+        Operation::new("get", "/admin/{list}", "Admin", "Locations or categories with their event counts")
+            .params(choice_list_param())
+            .response("200", html("The list with forms to add, rename, retire and restore"))
+            .response("303", redirect(not_logged_in))
+            .response("403", html("Not an admin")),
+        Operation::new("post", "/admin/{list}", "Admin", "Add a location or category")
+            .params(choice_list_param())
+            .body(form_body::<ChoiceNameData>())
+            .response("303", redirect("Added, redirects to the list"))
+            .response("403", html("Not an admin"))
+            .response("422", html("The list again with a message")),
+        Operation::new("post", "/admin/{list}/{id}/rename", "Admin", "Rename a location or category")
+            .description("Events using the old name are renamed with it.")
+            .params(choice_list_param())
+            .body(form_body::<ChoiceNameData>())
+            .response("303", redirect("Redirects to the list"))
+            .response("403", html("Not an admin"))
+            .response("404", html("No such entry")),
+        Operation::new("post", "/admin/{list}/{id}/retire", "Admin", "Stop offering a location or category")
+            .description("Existing events keep it, new and edited ones can't pick it.")
+            .params(choice_list_param())
+            .response("303", redirect("Redirects to the list"))
+            .response("403", html("Not an admin"))
+            .response("404", html("No such entry")),
+        Operation::new("post", "/admin/{list}/{id}/restore", "Admin", "Offer a retired location or category again")
+            .params(choice_list_param())
+            .response("303", redirect("Redirects to the list"))
+            .response("403", html("Not an admin"))
+            .response("404", html("No such entry")),
+
+        // flag_handler
+        Operation::new("post", "/event/{id}/flag", "Moderation", "Flag an event for moderators")
+            .description("One flag per user and event. Enough flags hide the event until a moderator restores it.")
+            .body(form_body::<NewFlagData>())
+            .response("303", redirect("Redirects to the event, or /login when not logged in"))
+            .response("404", html("No such event, or it is already hidden")),
+        Operation::new("get", "/admin/flags", "Moderation", "Flagged events, the most flagged first")
+            .response("200", html("Each flagged event with its flags"))
+            .response("303", redirect(not_logged_in))
+            .response("403", html("Not a moderator")),
+        Operation::new("post", "/admin/flags/{id}/restore", "Moderation", "Show a flagged event again and clear its flags")
+            .response("303", redirect("Redirects to /admin/flags"))
+            .response("403", html("Not a moderator"))
+            .response("404", html("No such event")),
+        Operation::new("post", "/admin/flags/{id}/remove", "Moderation", "Delete a flagged event")
+            .response("303", redirect("Redirects to /admin/flags"))
+            .response("403", html("Not a moderator"))
+            .response("404", html("No such event")),
+        Operation::new("post", "/admin/flags/{id}/dismiss", "Moderation", "Clear an event's flags without changing it")
+            .response("303", redirect("Redirects to /admin/flags"))
+            .response("403", html("Not a moderator"))
+            .response("404", html("No such event")),
+
+        // docs_handler
+        Operation::new("get", "/api/openapi.json", "Docs", "This document")
+            .response("200", text("application/json", "OpenAPI 3 document")),
+        Operation::new("get", "/api/docs", "Docs", "This document as a web page")
+            .response("200", html("Every route with its parameters and responses")),
+
+        // api_handler, nested under /api/v1
+        Operation::new("get", "/api/v1/events", "API", "List upcoming events")
+            .params(query_params::<EventFilter>(None))
+            .params(query_params::<Pagination>(None))
+            .response("200", json_response("One page of events, soonest first", "ApiEventPage"))
+            .response("422", api_error("Invalid filter"))
+            .api(),
+        Operation::new("post", "/api/v1/events", "API", "Create an event")
+            .body(json_body("NewEventData"))
+            .response("201", json_response("The new event, also at the Location header", "ApiEvent"))
+            .response("400", api_error("Body is not valid JSON"))
+            .response("401", api_error("Not authenticated"))
+            .response("403", api_error("The account's email isn't confirmed"))
+            .response("422", api_error("Validation failed, see `fields`"))
+            .api(),
+        Operation::new("get", "/api/v1/events/{id}", "API", "Get an event")
+            .response("200", json_response("The event", "ApiEvent"))
+            .response("404", api_error("No such event"))
+            .api(),
+        Operation::new("put", "/api/v1/events/{id}", "API", "Update an event")
+            .body(json_body("NewEventData"))
+            .response("200", json_response("The updated event", "ApiEvent"))
+            .response("400", api_error("Body is not valid JSON"))
+            .response("401", api_error("Not authenticated"))
+            .response("403", api_error("Not the creator"))
+            .response("404", api_error("No such event"))
+            .response("422", api_error("Validation failed, see `fields`"))
+            .api(),
+        Operation::new("delete", "/api/v1/events/{id}", "API", "Delete an event")
+            .response("204", json!({ "description": "Deleted" }))
+            .response("401", api_error("Not authenticated"))
+            .response("403", api_error("Not the creator"))
+            .response("404", api_error("No such event"))
+            .api(),
+        Operation::new("post", "/api/v1/events/{id}/rsvp", "API", "Toggle going")
+            .response("200", json_response("Whether the user is going now", "ApiRsvp"))
+            .response("401", api_error("Not authenticated"))
+            .response("404", api_error("No such event"))
+            .api(),
+        Operation::new("get", "/api/v1/me", "API", "The current user")
+            .response("200", json_response("The user, with a CSRF token when using the session cookie", "ApiUser"))
+            .response("401", api_error("Not authenticated"))
+            .api(),
+    ]
+}
+
+/// The OpenAPI 3 document served at `/api/openapi.json`.
+pub fn spec(base_url: &str) -> Value {
+    let mut paths = Map::new();
+    for operation in operations() {
+        let path = paths.entry(operation.path).or_insert_with(|| json!({}));
+        path[operation.method] = operation.to_json();
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Happening nu",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "The site's pages and form posts, and the JSON API under `/api/v1`. \
+                Requests that change something need the session's CSRF token, as a `csrf_token` \
+                form field or an `X-CSRF-Token` header, unless they use an API token.",
+        },
+        "servers": [{ "url": base_url }],
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "NewEventData": NewEventData::schema(),
+                "EventListItem": EventListItem::schema(),
+                "ApiEvent": ApiEvent::schema(),
+                "ApiEventPage": ApiEventPage::schema(),
+                "ApiRsvp": ApiRsvp::schema(),
+                "ApiUser": ApiUser::schema(),
+                "ApiError": error_schema(),
+            },
+            "securitySchemes": {
+                "bearerAuth": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "A personal API token from /settings",
+                },
+                "sessionCookie": {
+                    "type": "apiKey",
+                    "in": "cookie",
+                    "name": "id",
+                    "description": "The site's login session",
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::{body::Body, http::{header, Request, StatusCode}, Router};
+    use sea_orm::Database;
+    use tera::Tera;
+    use tower::ServiceExt;
+
+    use super::{operations, path_params, spec};
+    use crate::AppState;
+    use crate::mailer::Mailer;
+    use crate::router::routes;
+
+    /// Prefixes whose nested router has its own fallback, which axum also
+    /// lists as a path.
+    const NESTED_FALLBACKS: &[&str] = &["/api/v1"];
+
+    /// The site's routes as `main` mounts them. Anything answered by the
+    /// fallback didn't match a route.
+    async fn app() -> Router {
+        let app_state = AppState {
+            db_connection: Database::connect("sqlite::memory:").await.unwrap(),
+            tera: Tera::default(),
+            base_url: String::from("http://localhost:3000"),
+            flag_threshold: 3,
+            mailer: Mailer::to_dir(std::env::temp_dir().join("happeningnu-test-mail")),
+            trust_proxy: false,
+        };
+        routes()
+            .fallback(|| async { StatusCode::IM_A_TEAPOT })
+            .with_state(app_state)
+    }
+
+    /// `path` with its parameters filled in with values the handlers accept.
+    fn example_uri(path: &str) -> String {
+        let mut uri = path.to_string();
+        for name in path_params(path) {
+            let value = match name.as_str() {
+                "feed" => "token.ics",
+                "list" => "locations",
+                _ => "1",
+            };
+            uri = uri.replace(&format!("{{{}}}", name), value);
+        }
+        uri
+    }
+
+    /// (method, path) for every route the real router serves. axum can't
+    /// list its routes, but its `Debug` output names every path, and a
+    /// method a path doesn't take is answered with the ones it does.
+    async fn served_routes(app: &Router) -> BTreeSet<(String, String)> {
+        let debug = format!("{:?}", routes());
+        let paths: BTreeSet<String> = debug
+            .split("): \"")
+            .skip(1)
+            .filter_map(|rest| rest.split_once('"').map(|(path, _)| path.to_string()))
+            .filter(|path| !path.contains("__private__axum"))
+            .collect();
+        assert!(paths.contains("/login") && paths.contains("/api/v1/me"), "no paths in {}", debug);
+
+        let mut found = BTreeSet::new();
+        for path in paths {
+            let request = Request::builder()
+                .method("TRACE")
+                .uri(example_uri(&path))
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            if NESTED_FALLBACKS.contains(&path.as_str()) {
+                continue;
+            }
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "TRACE {}", path);
+            let allow = response.headers()[header::ALLOW].to_str().unwrap().to_lowercase();
+            for method in allow.split(',').map(str::trim).filter(|method| *method != "head") {
+                found.insert((method.to_string(), path.clone()));
+            }
+        }
+        found
+    }
+
+    #[tokio::test]
+    async fn spec_documents_exactly_the_served_routes() {
+        let documented: BTreeSet<(String, String)> = operations()
+            .iter()
+            .map(|op| (op.method.to_string(), op.path.to_string()))
+            .collect();
+        let served = served_routes(&app().await).await;
+
+        let undocumented: Vec<_> = served.difference(&documented).collect();
+        let stale: Vec<_> = documented.difference(&served).collect();
+        assert!(undocumented.is_empty(), "routes missing from the OpenAPI spec: {:?}", undocumented);
+        assert!(stale.is_empty(), "documented routes that aren't served: {:?}", stale);
+
+        let spec = spec("http://localhost:3000");
+        assert_eq!(spec["paths"].as_object().map(|p| p.len()), Some(
+            documented.iter().map(|(_, path)| path).collect::<BTreeSet<_>>().len()
+        ));
+    }
+
+    #[tokio::test]
+    async fn documented_routes_are_served() {
+        let app = app().await;
+        for op in operations() {
+            let uri = example_uri(op.path);
+            let request = Request::builder()
+                .method(op.method.to_uppercase().as_str())
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_ne!(response.status(), StatusCode::IM_A_TEAPOT, "{} {} is not routed", op.method, uri);
+            assert_ne!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{} {} is not routed", op.method, uri);
+        }
+    }
+
+    #[test]
+    fn form_fields_match_the_request_types() {
+        let spec = spec("http://localhost:3000");
+        let fields = |path: &str| -> BTreeSet<String> {
+            spec["paths"][path]["post"]["requestBody"]["content"]["application/x-www-form-urlencoded"]["schema"]["properties"]
+                .as_object()
+                .map(|properties| properties.keys().cloned().collect())
+                .unwrap_or_default()
+        };
+        let expected = |names: &[&str]| -> BTreeSet<String> {
+            names.iter().map(|name| name.to_string()).collect()
+        };
+
+        assert_eq!(fields("/login"), expected(&["email", "password", "csrf_token"]));
+        assert_eq!(fields("/signup"), expected(&["email", "username", "password", "confirm_password", "csrf_token"]));
+        assert_eq!(
+            fields("/new_event"),
+            expected(&["title", "url", "location", "date", "category", "description", "csrf_token"]),
+        );
+    }
+}
@@ -1,8 +1,19 @@
 use axum::{Router};
-use crate::{ AppState, handler::user_handler::user_router, handler::event_handler::event_router };
+use crate::{ AppState, handler::user_handler::user_router, handler::event_handler::event_router, handler::calendar_handler::calendar_router, handler::feed_handler::feed_router, handler::api_handler::api_router, handler::settings_handler::settings_router, handler::docs_handler::docs_router, handler::search_handler::search_router, handler::admin_handler::admin_router, handler::flag_handler::flag_router, handler::password_handler::password_router, handler::verification_handler::verification_router, handler::two_factor_handler::two_factor_router };
 
 pub fn routes() -> Router<AppState> {
     Router::new()
     .merge(user_router())
     .merge(event_router())
+    .nest("/api/v1", api_router())
+    .merge(calendar_router())
+    .merge(feed_router())
+    .merge(settings_router())
+    .merge(docs_router())
+    .merge(search_router())
+    .merge(admin_router())
+    .merge(flag_router())
+    .merge(password_router())
+    .merge(verification_router())
+    .merge(two_factor_router())
 }
\ No newline at end of file
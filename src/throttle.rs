@@ -0,0 +1,448 @@
+//! Failed login counters with exponential backoff and a temporary lockout,
+//! kept per account email and per client IP in `login_throttles`.
+
+use chrono::{Duration, NaiveDateTime};
+use sea_orm::{
+    sea_query::Expr,
+    ActiveModelTrait,
+    ColumnTrait,
+    ConnectionTrait,
+    DbBackend,
+    DbErr,
+    EntityTrait,
+    QueryFilter,
+    Set,
+    Statement,
+    TransactionTrait,
+};
+
+use crate::entities::login_throttles;
+use crate::entities::login_throttles::Entity as LoginThrottle;
+
+pub struct Policy {
+    /// Failures allowed before attempts have to wait
+    free_failures: i32,
+    /// Every this many failures locks the key for `lockout_minutes`
+    lockout_after: i32,
+    lockout_minutes: i64,
+    /// The backoff doubles with each failure up to this
+    max_delay_seconds: i64,
+}
+
+/// Someone guessing one account's password.
+pub const ACCOUNT: Policy = Policy {
+    free_failures: 3,
+    lockout_after: 10,
+    lockout_minutes: 15,
+    max_delay_seconds: 300,
+};
+
+/// One client trying many accounts. Looser, since a household or an office
+/// shares an address.
+pub const CLIENT: Policy = Policy {
+    free_failures: 10,
+    lockout_after: 50,
+    lockout_minutes: 15,
+    max_delay_seconds: 300,
+};
+
+/// Failures are forgotten once a key has been quiet this long.
+const FORGET_AFTER_HOURS: i64 = 24;
+
+/// Why an attempt was turned away and for how much longer. A lockout
+/// outranks a backoff, then the longer wait wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Blocked {
+    Wait(Duration),
+    Locked(Duration),
+}
+
+impl Blocked {
+    pub fn message(&self) -> String {
+        match self {
+            Blocked::Wait(wait) => format!("Too many failed logins. Try again in {}.", duration_text(*wait)),
+            Blocked::Locked(wait) => format!(
+                "Too many failed logins, so logging in is locked for {}. A password reset works meanwhile.",
+                duration_text(*wait),
+            ),
+        }
+    }
+}
+
+fn duration_text(duration: Duration) -> String {
+    let seconds = ((duration.num_milliseconds() + 999) / 1000).max(1);
+    if seconds < 60 {
+        format!("{} second{}", seconds, if seconds == 1 { "" } else { "s" })
+    } else {
+        let minutes = (seconds + 59) / 60;
+        format!("{} minute{}", minutes, if minutes == 1 { "" } else { "s" })
+    }
+}
+
+/// Emails that don't belong to an account are counted too, so the answer
+/// never tells them apart.
+pub fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+pub fn client_key(ip: &str) -> String {
+    format!("client:{}", ip)
+}
+
+/// How long to wait after the `failures`th failure: nothing for the free
+/// ones, then 1, 2, 4... seconds up to the policy's maximum.
+fn backoff(policy: &Policy, failures: i32) -> Duration {
+    if failures <= policy.free_failures {
+        return Duration::zero();
+    }
+    let doublings = (failures - policy.free_failures - 1).min(30) as u32;
+    Duration::seconds((1i64 << doublings).min(policy.max_delay_seconds))
+}
+
+/// The block `row` still imposes at `now`, if any.
+fn block_of(row: &login_throttles::Model, now: NaiveDateTime) -> Option<Blocked> {
+    if let Some(locked_until) = row.locked_until
+        && locked_until > now
+    {
+        return Some(Blocked::Locked(locked_until - now));
+    }
+    if row.retry_after > now {
+        return Some(Blocked::Wait(row.retry_after - now));
+    }
+    None
+}
+
+/// Counts a login attempt against every key before the password is
+/// checked, unless one of them is still blocked, in which case nothing is
+/// counted and the block is returned. Counting first means a burst of
+/// parallel requests can't all reach the slow password check before the
+/// first failure is written; `login_succeeded` takes the count back.
+pub async fn begin_attempt<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    keys: &[(&str, &Policy)],
+    now: NaiveDateTime,
+) -> Result<Option<Blocked>, DbErr> {
+    // Writing first takes SQLite's write lock up front, so parallel attempts
+    // queue behind each other instead of checking the same stale rows
+    let txn = db.begin().await?;
+    let mut longest = None;
+    let mut rows = Vec::with_capacity(keys.len());
+    for (key, policy) in keys {
+        let row = count_failure(&txn, key, now).await?;
+        longest = longest.max(block_of(&row, now));
+        rows.push((row, *policy));
+    }
+    if longest.is_some() {
+        txn.rollback().await?;
+        return Ok(longest);
+    }
+    for (row, policy) in rows {
+        set_block(&txn, row, policy, now).await?;
+    }
+    txn.commit().await?;
+    Ok(None)
+}
+
+/// Adds one to `key`'s failures in a single statement, so parallel attempts
+/// can't both insert the row or overwrite each other's count. The returned
+/// row still carries the block it had before this failure.
+async fn count_failure<C: ConnectionTrait>(db: &C, key: &str, now: NaiveDateTime) -> Result<login_throttles::Model, DbErr> {
+    let statement = Statement::from_sql_and_values(
+        DbBackend::Sqlite,
+        r#"INSERT INTO "login_throttles" ("key", "failures", "last_failure_at", "retry_after", "locked_until")
+            VALUES (?, 1, ?, ?, NULL)
+            ON CONFLICT ("key") DO UPDATE SET
+                "failures" = CASE WHEN "login_throttles"."last_failure_at" > ?
+                    THEN "login_throttles"."failures" + 1 ELSE 1 END,
+                "last_failure_at" = "excluded"."last_failure_at"
+            RETURNING *"#,
+        [
+            key.into(),
+            now.into(),
+            now.into(),
+            (now - Duration::hours(FORGET_AFTER_HOURS)).into(),
+        ],
+    );
+    LoginThrottle::find()
+        .from_raw_sql(statement)
+        .one(db)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(key.to_string()))
+}
+
+/// Sets the backoff and lockout that follow `row`'s latest failure.
+async fn set_block<C: ConnectionTrait>(
+    db: &C,
+    row: login_throttles::Model,
+    policy: &Policy,
+    now: NaiveDateTime,
+) -> Result<(), DbErr> {
+    let still_locked = row.locked_until.filter(|until| *until > now);
+    let locked_until = if row.failures % policy.lockout_after == 0 {
+        Some(now + Duration::minutes(policy.lockout_minutes))
+    } else {
+        still_locked
+    };
+    let retry_after = now + backoff(policy, row.failures);
+
+    let mut active_model: login_throttles::ActiveModel = row.into();
+    active_model.retry_after = Set(retry_after);
+    active_model.locked_until = Set(locked_until);
+    let _ = active_model.update(db).await?;
+    Ok(())
+}
+
+/// A login went through: the account starts over, and the client gets back
+/// the attempt `begin_attempt` counted without losing an earlier lockout.
+pub async fn login_succeeded<C: ConnectionTrait>(
+    db: &C,
+    account: &str,
+    client: &str,
+    now: NaiveDateTime,
+) -> Result<(), DbErr> {
+    let _ = LoginThrottle::delete_many()
+        .filter(login_throttles::Column::Key.eq(account))
+        .exec(db)
+        .await?;
+    // Taken back in the statement itself so a parallel failure isn't lost
+    let _ = LoginThrottle::update_many()
+        .col_expr(login_throttles::Column::Failures, Expr::cust(r#"MAX("failures" - 1, 0)"#))
+        .col_expr(login_throttles::Column::RetryAfter, Expr::value(now))
+        .filter(login_throttles::Column::Key.eq(client))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// A finished password reset proves who owns the account, so its failures
+/// and any lockout are dropped, as the lockout message promises.
+pub async fn lift_lockout<C: ConnectionTrait>(db: &C, account: &str) -> Result<(), DbErr> {
+    let _ = LoginThrottle::delete_many()
+        .filter(login_throttles::Column::Key.eq(account))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Drops counters that have been quiet long enough to be forgotten anyway,
+/// so guessed emails don't pile up. Run now and then.
+pub async fn forget_old<C: ConnectionTrait>(db: &C, now: NaiveDateTime) -> Result<u64, DbErr> {
+    let result = LoginThrottle::delete_many()
+        .filter(login_throttles::Column::LastFailureAt.lt(now - Duration::hours(FORGET_AFTER_HOURS)))
+        .filter(
+            login_throttles::Column::LockedUntil
+                .is_null()
+                .or(login_throttles::Column::LockedUntil.lt(now)),
+        )
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use futures::future::join_all;
+    use sea_orm::{Database, DatabaseConnection, Schema};
+
+    use super::*;
+
+    async fn setup_db(url: &str) -> DatabaseConnection {
+        let db = Database::connect(url).await.unwrap();
+        let schema = Schema::new(db.get_database_backend());
+        let statement = schema.create_table_from_entity(login_throttles::Entity);
+        db.execute(db.get_database_backend().build(&statement)).await.unwrap();
+        db
+    }
+
+    fn start() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 17).unwrap().and_hms_opt(12, 0, 0).unwrap()
+    }
+
+    /// Fires `count` attempts 10ms apart, as fast as a script would, and
+    /// returns how many got through to the password check.
+    async fn burst(db: &DatabaseConnection, keys: &[(&str, &Policy)], now: &mut NaiveDateTime, count: usize) -> usize {
+        let mut allowed = 0;
+        for _ in 0..count {
+            if begin_attempt(db, keys, *now).await.unwrap().is_none() {
+                allowed += 1;
+            }
+            *now += Duration::milliseconds(10);
+        }
+        allowed
+    }
+
+    /// The block on `key` at `now`, if any.
+    async fn blocked<C: ConnectionTrait>(db: &C, key: &str, now: NaiveDateTime) -> Result<Option<Blocked>, DbErr> {
+        let row = LoginThrottle::find()
+            .filter(login_throttles::Column::Key.eq(key))
+            .one(db)
+            .await?;
+        Ok(row.and_then(|row| block_of(&row, now)))
+    }
+
+    /// Adds a failure to `key` and works out when it may try again.
+    async fn record_failure<C: ConnectionTrait + TransactionTrait>(
+        db: &C,
+        key: &str,
+        policy: &Policy,
+        now: NaiveDateTime,
+    ) -> Result<(), DbErr> {
+        let txn = db.begin().await?;
+        let row = count_failure(&txn, key, now).await?;
+        set_block(&txn, row, policy, now).await?;
+        txn.commit().await
+    }
+
+    #[tokio::test]
+    async fn burst_against_one_account_backs_off_then_locks() {
+        let db = setup_db("sqlite::memory:").await;
+        let account = account_key("Alice@Example.com ");
+        let client = client_key("203.0.113.7");
+        let keys = [(account.as_str(), &ACCOUNT), (client.as_str(), &CLIENT)];
+        let mut now = start();
+
+        // A second's worth of requests: the free attempts plus the one that starts the backoff
+        assert_eq!(burst(&db, &keys, &mut now, 100).await, 4);
+        assert!(matches!(begin_attempt(&db, &keys, now).await.unwrap(), Some(Blocked::Wait(_))));
+
+        // Waiting out each backoff lets exactly one more through, and the next wait doubles
+        let mut delays = vec![];
+        while let Some(Blocked::Wait(wait)) = begin_attempt(&db, &keys, now).await.unwrap() {
+            now += wait;
+            assert_eq!(burst(&db, &keys, &mut now, 50).await, 1);
+            let row = LoginThrottle::find()
+                .filter(login_throttles::Column::Key.eq(account.as_str()))
+                .one(&db)
+                .await
+                .unwrap()
+                .unwrap();
+            delays.push((row.retry_after - row.last_failure_at).num_seconds());
+        }
+        assert_eq!(delays, vec![2, 4, 8, 16, 32, 64]);
+
+        let Some(Blocked::Locked(lock)) = begin_attempt(&db, &keys, now).await.unwrap() else {
+            panic!("ten failures should lock the account");
+        };
+        assert!(lock > Duration::minutes(14) && lock <= Duration::minutes(15));
+        now += lock;
+        assert!(begin_attempt(&db, &keys, now).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn parallel_attempts_are_all_counted() {
+        let path = std::env::temp_dir().join(format!("happeningnu-throttle-parallel-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = setup_db(&format!("sqlite://{}?mode=rwc", path.display())).await;
+        let account = account_key("alice@example.com");
+        let now = start();
+
+        // Nobody is held back, so every attempt has to show up in the count
+        let patient = Policy { free_failures: 100, lockout_after: 100, lockout_minutes: 15, max_delay_seconds: 300 };
+        let clients: Vec<String> = (0..20).map(|i| client_key(&format!("203.0.113.{}", i))).collect();
+        let attempts: Vec<_> = clients.iter().map(|client| [(account.as_str(), &patient), (client.as_str(), &CLIENT)]).collect();
+        let results = join_all(attempts.iter().map(|keys| begin_attempt(&db, keys, now))).await;
+        assert!(results.iter().all(|result| matches!(result, Ok(None))));
+        let row = LoginThrottle::find()
+            .filter(login_throttles::Column::Key.eq(account.as_str()))
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.failures, 20);
+
+        // With the real policy only the free attempts and the one starting the backoff get through
+        let other = account_key("bob@example.com");
+        let attempts: Vec<_> = clients.iter().map(|client| [(other.as_str(), &ACCOUNT), (client.as_str(), &CLIENT)]).collect();
+        let results = join_all(attempts.iter().map(|keys| begin_attempt(&db, keys, now))).await;
+        let allowed = results.iter().filter(|result| matches!(result, Ok(None))).count();
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(allowed, ACCOUNT.free_failures as usize + 1);
+
+        db.close().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn burst_across_many_accounts_from_one_client_is_throttled() {
+        let db = setup_db("sqlite::memory:").await;
+        let client = client_key("203.0.113.7");
+        let mut now = start();
+
+        let mut allowed = 0;
+        for i in 0..100 {
+            let account = account_key(&format!("user{}@example.com", i));
+            let keys = [(account.as_str(), &ACCOUNT), (client.as_str(), &CLIENT)];
+            allowed += burst(&db, &keys, &mut now, 1).await;
+        }
+        assert_eq!(allowed, 11);
+
+        // Someone else's account from another address is unaffected
+        let other = client_key("198.51.100.2");
+        let account = account_key("user1@example.com");
+        assert!(begin_attempt(&db, &[(account.as_str(), &ACCOUNT), (other.as_str(), &CLIENT)], now).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn success_resets_the_account_but_not_the_client() {
+        let db = setup_db("sqlite::memory:").await;
+        let account = account_key("alice@example.com");
+        let client = client_key("203.0.113.7");
+        let keys = [(account.as_str(), &ACCOUNT), (client.as_str(), &CLIENT)];
+        let mut now = start();
+
+        assert_eq!(burst(&db, &keys, &mut now, 3).await, 3);
+        login_succeeded(&db, &account, &client, now).await.unwrap();
+        assert_eq!(burst(&db, &keys, &mut now, 4).await, 4);
+
+        let rows = LoginThrottle::find().all(&db).await.unwrap();
+        let client_row = rows.iter().find(|row| row.key == client).unwrap();
+        assert_eq!(client_row.failures, 6);
+    }
+
+    #[tokio::test]
+    async fn counters_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("happeningnu-throttle-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let account = account_key("alice@example.com");
+        let client = client_key("203.0.113.7");
+        let keys = [(account.as_str(), &ACCOUNT), (client.as_str(), &CLIENT)];
+        let mut now = start();
+
+        let db = setup_db(&url).await;
+        for _ in 0..ACCOUNT.lockout_after {
+            record_failure(&db, &account, &ACCOUNT, now).await.unwrap();
+        }
+        db.close().await.unwrap();
+
+        let db = Database::connect(&url).await.unwrap();
+        now += Duration::minutes(1);
+        assert!(matches!(begin_attempt(&db, &keys, now).await.unwrap(), Some(Blocked::Locked(_))));
+        db.close().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn quiet_keys_are_forgotten() {
+        let db = setup_db("sqlite::memory:").await;
+        let account = account_key("alice@example.com");
+        let mut now = start();
+
+        for _ in 0..ACCOUNT.lockout_after - 1 {
+            record_failure(&db, &account, &ACCOUNT, now).await.unwrap();
+        }
+        now += Duration::hours(FORGET_AFTER_HOURS);
+        record_failure(&db, &account, &ACCOUNT, now).await.unwrap();
+        assert!(blocked(&db, &account, now).await.unwrap().is_none());
+
+        now += Duration::hours(FORGET_AFTER_HOURS + 1);
+        assert_eq!(forget_old(&db, now).await.unwrap(), 1);
+    }
+
+    #[test]
+    fn messages_round_up_the_wait() {
+        assert_eq!(Blocked::Wait(Duration::milliseconds(1500)).message(), "Too many failed logins. Try again in 2 seconds.");
+        assert!(Blocked::Locked(Duration::seconds(61)).message().contains("locked for 2 minutes"));
+    }
+}